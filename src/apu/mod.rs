@@ -0,0 +1,474 @@
+use std::collections::VecDeque;
+use self::square::*;
+use self::wave::*;
+use self::noise::*;
+
+mod square;
+mod wave;
+mod noise;
+
+// The APU always runs off the normal speed clock.
+const CLOCK_RATE: u32 = 4194304;
+// The frame sequencer clocks lengths, sweep and envelopes at 512 Hz.
+const FRAME_SEQUENCER_CYCLES: u32 = CLOCK_RATE / 512;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+// Bits that always read back as 1 for each register from NR10 (0xFF10) to
+// 0xFF2F, whether they're write-only or don't exist at all.
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // (unused), NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // (unused), NR41-NR44
+    0x00, 0x00, 0x70,             // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF
+];
+
+// Counts down to silencing a channel, when enabled by bit 6 of NRx4.
+pub struct LengthCounter {
+    max: u16,
+    counter: u16,
+    enabled: bool
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> Self {
+        LengthCounter {
+            max: max,
+            counter: 0,
+            enabled: false
+        }
+    }
+
+    pub fn load(&mut self, value: u16) {
+        self.counter = self.max - value;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    // True when the counter has just run out, meaning the channel turns off.
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+}
+
+// Volume envelope, as set by NRx2.
+#[derive(Default)]
+pub struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8
+}
+
+impl Envelope {
+    pub fn write(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = value & 0b0000_1000u8 != 0;
+        self.period = value & 0b0000_0111u8;
+    }
+
+    // The DAC is only off when NRx2's top 5 bits are all clear.
+    pub fn is_dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+}
+
+// Owns the sound registers (0xFF10-0xFF26) and wave RAM (0xFF30-0xFF3F), and
+// mixes the 4 channels down into stereo samples for the frontend to pull.
+pub struct Apu {
+    // What reads back from each register, which is just what was last written.
+    registers: Vec<u8>,
+    wave_ram: Vec<u8>,
+    powered: bool,
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    frame_sequencer_cycles: u32,
+    frame_sequencer_step: u8,
+    sample_rate: u32,
+    // Accumulates sample_rate per cycle, so a sample is due every CLOCK_RATE.
+    sample_clock: u64,
+    // Interleaved left and right samples from -1.0 to 1.0.
+    samples: VecDeque<f32>,
+    // High-pass filter state per side, which removes the DACs' DC offset.
+    capacitors: (f32, f32),
+    charge_factor: f32
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        let mut apu = Apu {
+            registers: vec![0x0; 0x20],
+            wave_ram: vec![0x0; 0x10],
+            powered: false,
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            frame_sequencer_cycles: 0,
+            frame_sequencer_step: 0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_clock: 0,
+            samples: VecDeque::new(),
+            capacitors: (0.0, 0.0),
+            charge_factor: 0.0
+        };
+        apu.set_sample_rate(DEFAULT_SAMPLE_RATE);
+        apu
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.sample_clock = 0;
+        self.samples.clear();
+        self.charge_factor = 0.999958f32.powf(CLOCK_RATE as f32 / sample_rate as f32);
+    }
+
+    // Fills buffer with as many interleaved stereo samples as are ready, and
+    // returns how many that was.
+    pub fn pull_samples(&mut self, buffer: &mut [f32]) -> usize {
+        let count = buffer.len().min(self.samples.len());
+        for (slot, sample) in buffer.iter_mut().zip(self.samples.drain(.. count)) {
+            *slot = sample;
+        }
+        count
+    }
+
+    fn read_status(&self) -> u8 {
+        READ_MASKS[0x16]
+            | (self.powered as u8) << 7
+            | (self.noise.is_enabled() as u8) << 3
+            | (self.wave.is_enabled() as u8) << 2
+            | (self.square2.is_enabled() as u8) << 1
+            | self.square1.is_enabled() as u8
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF26 => self.read_status(),
+            0xFF10 ... 0xFF2F => {
+                let offset = (addr - 0xFF10) as usize;
+                READ_MASKS[offset] | self.registers[offset]
+            },
+            0xFF30 ... 0xFF3F => self.wave_ram[(addr - 0xFF30) as usize],
+            _ => unreachable!("APU registers only span 0xFF10-0xFF3F.")
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF26 => {
+                let powered = value & 0b1000_0000u8 != 0;
+                if self.powered && !powered {
+                    // Powering off clears every sound register.
+                    for register in self.registers.iter_mut() {
+                        *register = 0x0;
+                    }
+                    self.square1 = Square::new(true);
+                    self.square2 = Square::new(false);
+                    self.wave = Wave::new();
+                    self.noise = Noise::new();
+                } else if !self.powered && powered {
+                    self.frame_sequencer_step = 0;
+                    self.frame_sequencer_cycles = 0;
+                }
+                self.powered = powered;
+            },
+            // Everything but NR52 is frozen while the APU is off.
+            0xFF10 ... 0xFF2F if !self.powered => (),
+            0xFF10 ... 0xFF2F => {
+                self.registers[(addr - 0xFF10) as usize] = value;
+                match addr {
+                    0xFF10 ... 0xFF14 => self.square1.write((addr - 0xFF10) as usize, value),
+                    0xFF15 ... 0xFF19 => self.square2.write((addr - 0xFF15) as usize, value),
+                    0xFF1A ... 0xFF1E => self.wave.write((addr - 0xFF1A) as usize, value),
+                    0xFF1F ... 0xFF23 => self.noise.write((addr - 0xFF1F) as usize, value),
+                    _ => ()
+                }
+            },
+            // Wave RAM stays accessible regardless of power.
+            0xFF30 ... 0xFF3F => self.wave_ram[(addr - 0xFF30) as usize] = value,
+            _ => unreachable!("APU registers only span 0xFF10-0xFF3F.")
+        }
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        match self.frame_sequencer_step {
+            0 | 4 => self.clock_lengths(),
+            2 | 6 => {
+                self.clock_lengths();
+                self.square1.clock_sweep();
+            },
+            7 => {
+                self.square1.clock_envelope();
+                self.square2.clock_envelope();
+                self.noise.clock_envelope();
+            },
+            _ => ()
+        }
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    fn clock_lengths(&mut self) {
+        self.square1.clock_length();
+        self.square2.clock_length();
+        self.wave.clock_length();
+        self.noise.clock_length();
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if self.powered {
+            self.square1.tick(cycles);
+            self.square2.tick(cycles);
+            self.wave.tick(cycles);
+            self.noise.tick(cycles);
+            self.frame_sequencer_cycles += cycles;
+            while self.frame_sequencer_cycles >= FRAME_SEQUENCER_CYCLES {
+                self.frame_sequencer_cycles -= FRAME_SEQUENCER_CYCLES;
+                self.step_frame_sequencer();
+            }
+        }
+        // Silence still gets sampled while powered off, so the output stream keeps up.
+        self.sample_clock += cycles as u64 * self.sample_rate as u64;
+        while self.sample_clock >= CLOCK_RATE as u64 {
+            self.sample_clock -= CLOCK_RATE as u64;
+            let (left, right) = self.mix();
+            // Anything the frontend hasn't pulled within a second is stale.
+            if self.samples.len() >= self.sample_rate as usize * 2 {
+                self.samples.pop_front();
+                self.samples.pop_front();
+            }
+            self.samples.push_back(left);
+            self.samples.push_back(right);
+        }
+    }
+
+    fn high_pass(capacitor: &mut f32, charge_factor: f32, input: f32) -> f32 {
+        let output = input - *capacitor;
+        *capacitor = input - output * charge_factor;
+        output
+    }
+
+    fn mix(&mut self) -> (f32, f32) {
+        if !self.powered {
+            return (0.0, 0.0);
+        }
+        let channels = [
+            (self.square1.is_dac_enabled(), self.square1.output()),
+            (self.square2.is_dac_enabled(), self.square2.output()),
+            (self.wave.is_dac_enabled(), self.wave.output(&self.wave_ram)),
+            (self.noise.is_dac_enabled(), self.noise.output())
+        ];
+        let nr50 = self.registers[0x14];
+        let nr51 = self.registers[0x15];
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, &(dac_enabled, output)) in channels.iter().enumerate() {
+            if !dac_enabled {
+                continue;
+            }
+            // Each DAC maps 0-15 onto 1.0 down to -1.0.
+            let analog = 1.0 - output as f32 / 7.5;
+            if nr51 & (0b0001_0000u8 << i) != 0 {
+                left += analog;
+            }
+            if nr51 & (0b0000_0001u8 << i) != 0 {
+                right += analog;
+            }
+        }
+        // NR50 scales each side by 1/8 to 8/8, and the 4 channels share the range.
+        let left = left * (((nr50 >> 4) & 0b111) + 1) as f32 / 32.0;
+        let right = right * ((nr50 & 0b111) + 1) as f32 / 32.0;
+        (
+            Self::high_pass(&mut self.capacitors.0, self.charge_factor, left),
+            Self::high_pass(&mut self.capacitors.1, self.charge_factor, right)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered_apu() -> Apu {
+        let mut apu = Apu::new();
+        apu.write(0xFF26, 0x80);
+        apu
+    }
+
+    // Bit 0 of NR52 is channel 1's status.
+    fn is_square1_on(apu: &Apu) -> bool {
+        apu.read(0xFF26) & 0b0000_0001u8 != 0
+    }
+
+    #[test]
+    fn length_counter_silences_the_channel() {
+        let mut apu = powered_apu();
+        apu.write(0xFF11, 0x3F);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0xC0);
+        assert!(is_square1_on(&apu));
+        // Step 0 of the frame sequencer clocks lengths.
+        apu.tick(FRAME_SEQUENCER_CYCLES);
+        assert!(!is_square1_on(&apu));
+
+        // Without bit 6 of NR14, the length is ignored.
+        let mut apu = powered_apu();
+        apu.write(0xFF11, 0x3F);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0x80);
+        apu.tick(FRAME_SEQUENCER_CYCLES * 8);
+        assert!(is_square1_on(&apu));
+    }
+
+    #[test]
+    fn envelope_steps_once_per_period() {
+        let mut envelope = Envelope::default();
+        envelope.write(0x0B);
+        envelope.trigger();
+        for _ in 0 .. 2 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 0);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 1);
+
+        // Decreasing stops at 0, and increasing at 15.
+        let mut envelope = Envelope::default();
+        envelope.write(0x11);
+        envelope.trigger();
+        for _ in 0 .. 4 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 0);
+        envelope.write(0xF9);
+        envelope.trigger();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+    }
+
+    #[test]
+    fn sweep_overflow_disables_channel_1() {
+        // Period 1, adding a half each time, from 0x400.
+        let mut apu = powered_apu();
+        apu.write(0xFF10, 0x11);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF13, 0x00);
+        apu.write(0xFF14, 0x84);
+        apu.tick(FRAME_SEQUENCER_CYCLES * 2);
+        assert!(is_square1_on(&apu));
+        // Step 2 moves it to 0x600, which would overflow on the next sweep.
+        apu.tick(FRAME_SEQUENCER_CYCLES);
+        assert!(!is_square1_on(&apu));
+
+        // The overflow check also runs on trigger.
+        let mut apu = powered_apu();
+        apu.write(0xFF10, 0x11);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF13, 0xFF);
+        apu.write(0xFF14, 0x87);
+        assert!(!is_square1_on(&apu));
+    }
+
+    // The noise channel's output for each LFSR step, at full volume.
+    fn noise_outputs(nr43: u8, steps: usize) -> Vec<u8> {
+        let mut noise = Noise::new();
+        noise.write(2, 0xF0);
+        // The shortest period, 8 cycles.
+        noise.write(3, nr43);
+        noise.write(4, 0x80);
+        (0 .. steps).map(|_| {
+            noise.tick(8);
+            noise.output()
+        }).collect()
+    }
+
+    #[test]
+    fn noise_lfsr_sequences() {
+        // Starting from all 1s, it takes 15 steps for a 0 to reach bit 0.
+        let outputs = noise_outputs(0x00, 0x7FFF * 2);
+        assert!(outputs[.. 14].iter().all(|&output| output == 0));
+        assert_eq!(outputs[14], 15);
+        assert_eq!(&outputs[.. 0x7FFF], &outputs[0x7FFF ..]);
+
+        // 7-bit mode feeds back into bit 6 as well, so it's only 7 steps and a
+        // 127 step loop.
+        let outputs = noise_outputs(0x08, 127 * 2);
+        assert!(outputs[.. 6].iter().all(|&output| output == 0));
+        assert_eq!(outputs[6], 15);
+        assert_eq!(&outputs[.. 127], &outputs[127 ..]);
+    }
+
+    #[test]
+    fn wave_plays_wave_ram_at_the_selected_volume() {
+        // Samples counting from 0 to 15, twice over.
+        let wave_ram: Vec<u8> = (0 .. 0x10).map(|i| (i * 2 % 16) << 4 | (i * 2 + 1) % 16).collect();
+        let mut wave = Wave::new();
+        wave.write(0, 0x80);
+        wave.write(2, 0x20);
+        // Frequency 0x7FF, so each sample lasts 2 cycles.
+        wave.write(3, 0xFF);
+        wave.write(4, 0x87);
+        let samples: Vec<u8> = (0 .. 32).map(|_| {
+            let sample = wave.output(&wave_ram);
+            wave.tick(2);
+            sample
+        }).collect();
+        assert_eq!(samples, (0 .. 32).map(|i| (i % 16) as u8).collect::<Vec<u8>>());
+
+        // 25% volume shifts each sample right by 2.
+        wave.write(2, 0x60);
+        wave.tick(2 * 31);
+        assert_eq!(wave.output(&wave_ram), 0x0F >> 2);
+        // Turning the DAC off silences it until it's triggered again.
+        wave.write(0, 0x00);
+        assert!(!wave.is_enabled());
+    }
+}
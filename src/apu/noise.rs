@@ -0,0 +1,114 @@
+use super::*;
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Channel 4, which clocks a linear feedback shift register. Register numbers
+// are relative to 0xFF1F, so NR41 is 1.
+pub struct Noise {
+    enabled: bool,
+    clock_shift: u8,
+    // 7-bit mode feeds back into bit 6 as well, for a shorter, more tonal loop.
+    short_mode: bool,
+    divisor_code: u8,
+    timer: u32,
+    lfsr: u16,
+    length: LengthCounter,
+    envelope: Envelope
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            enabled: false,
+            clock_shift: 0,
+            short_mode: false,
+            divisor_code: 0,
+            timer: 0,
+            lfsr: 0x7FFF,
+            length: LengthCounter::new(64),
+            envelope: Envelope::default()
+        }
+    }
+
+    pub fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => (),
+            1 => self.length.load((value & 0b0011_1111u8) as u16),
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => {
+                self.clock_shift = value >> 4;
+                self.short_mode = value & 0b0000_1000u8 != 0;
+                self.divisor_code = value & 0b0000_0111u8;
+            },
+            4 => {
+                self.length.set_enabled(value & 0b0100_0000u8 != 0);
+                if value & 0b1000_0000u8 != 0 {
+                    self.trigger();
+                }
+            },
+            _ => unreachable!("The noise channel only has 4 registers.")
+        }
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[self.divisor_code as usize] << self.clock_shift
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.length.trigger();
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    fn step_lfsr(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | feedback << 14;
+        if self.short_mode {
+            self.lfsr = (self.lfsr & !(1 << 6)) | feedback << 6;
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.step_lfsr();
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    // The output is high whenever bit 0 is clear.
+    pub fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume()
+        } else {
+            0
+        }
+    }
+}
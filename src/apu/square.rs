@@ -0,0 +1,194 @@
+use super::*;
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0]  // 75%
+];
+
+// Channel 1's frequency sweep, as set by NR10.
+#[derive(Default)]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    shadow_frequency: u16
+}
+
+impl Sweep {
+    fn write(&mut self, value: u8) {
+        self.period = (value >> 4) & 0b0000_0111u8;
+        self.negate = value & 0b0000_1000u8 != 0;
+        self.shift = value & 0b0000_0111u8;
+    }
+
+    fn reload_timer(&mut self) {
+        // A period of 0 counts as 8.
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    fn calculate(&self) -> u16 {
+        let delta = self.shadow_frequency >> self.shift;
+        if self.negate {
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        }
+    }
+
+    // Returns false if the new frequency overflowed, which disables the channel.
+    fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow_frequency = frequency;
+        self.reload_timer();
+        self.enabled = self.period != 0 || self.shift != 0;
+        self.shift == 0 || self.calculate() <= 2047
+    }
+
+    fn clock(&mut self, frequency: &mut u16) -> bool {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer != 0 {
+            return true;
+        }
+        self.reload_timer();
+        if !self.enabled || self.period == 0 {
+            return true;
+        }
+        let new_frequency = self.calculate();
+        if new_frequency > 2047 {
+            return false;
+        }
+        if self.shift != 0 {
+            self.shadow_frequency = new_frequency;
+            *frequency = new_frequency;
+            // The overflow check runs a second time with the new frequency.
+            return self.calculate() <= 2047;
+        }
+        true
+    }
+}
+
+// Channels 1 and 2. Register numbers are relative to NR10 or 0xFF15.
+pub struct Square {
+    enabled: bool,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>
+}
+
+impl Square {
+    pub fn new(has_sweep: bool) -> Self {
+        Square {
+            enabled: false,
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            sweep: if has_sweep { Some(Sweep::default()) } else { None }
+        }
+    }
+
+    pub fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => {
+                if let Some(ref mut sweep) = self.sweep {
+                    sweep.write(value);
+                }
+            },
+            1 => {
+                self.duty = value >> 6;
+                self.length.load((value & 0b0011_1111u8) as u16);
+            },
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((value & 0b0000_0111u8) as u16) << 8;
+                self.length.set_enabled(value & 0b0100_0000u8 != 0);
+                if value & 0b1000_0000u8 != 0 {
+                    self.trigger();
+                }
+            },
+            _ => unreachable!("Square channels only have 5 registers.")
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.length.trigger();
+        self.timer = self.period();
+        self.envelope.trigger();
+        let frequency = self.frequency;
+        if let Some(ref mut sweep) = self.sweep {
+            if !sweep.trigger(frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let mut frequency = self.frequency;
+        let in_range = match self.sweep {
+            Some(ref mut sweep) => sweep.clock(&mut frequency),
+            None => true
+        };
+        self.frequency = frequency;
+        if !in_range {
+            self.enabled = false;
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.enabled {
+            DUTY_PATTERNS[self.duty as usize][self.duty_step as usize] * self.envelope.volume()
+        } else {
+            0
+        }
+    }
+}
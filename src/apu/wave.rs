@@ -0,0 +1,101 @@
+use super::*;
+
+// Channel 3, which plays back the 32 4-bit samples in wave RAM. Register
+// numbers are relative to NR30.
+pub struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+    // NR32's output level: mute, 100%, 50% or 25%.
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    length: LengthCounter
+}
+
+impl Wave {
+    pub fn new() -> Self {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            length: LengthCounter::new(256)
+        }
+    }
+
+    pub fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => {
+                self.dac_enabled = value & 0b1000_0000u8 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            },
+            1 => self.length.load(value as u16),
+            2 => self.volume_code = (value >> 5) & 0b0000_0011u8,
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((value & 0b0000_0111u8) as u16) << 8;
+                self.length.set_enabled(value & 0b0100_0000u8 != 0);
+                if value & 0b1000_0000u8 != 0 {
+                    self.trigger();
+                }
+            },
+            _ => unreachable!("The wave channel only has 5 registers.")
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn output(&self, wave_ram: &[u8]) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        // Each byte holds 2 samples, high nibble first.
+        let byte = wave_ram[self.position as usize / 2];
+        let sample = if self.position % 2 == 0 { byte >> 4 } else { byte & 0x0F };
+        match self.volume_code {
+            0 => 0,
+            1 => sample,
+            2 => sample >> 1,
+            3 => sample >> 2,
+            _ => unreachable!()
+        }
+    }
+}
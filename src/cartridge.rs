@@ -0,0 +1,146 @@
+use util::*;
+use mbc::*;
+use mbc::mbc1::*;
+use mbc::mbc2::*;
+use mbc::mbc3::*;
+use mbc::mbc5::*;
+use gameboy::*;
+use header::*;
+
+// Everything on the cartridge side of the bus: ROM at 0x0000-0x7FFF and
+// external RAM (or whatever the MBC maps there) at 0xA000-0xBFFF.
+pub trait Cartridge {
+    fn read_rom(&self, addr: u16) -> u8;
+
+    // Writes to ROM addresses never change ROM; they're how MBC registers get set.
+    fn write_rom(&mut self, addr: u16, value: u8);
+
+    fn read_ram(&self, addr: u16) -> u8;
+
+    fn write_ram(&mut self, addr: u16, value: u8);
+
+    // Contents to persist to the save file, including any RTC state.
+    fn save_ram(&self) -> Vec<u8>;
+
+    fn load_save_ram(&mut self, data: &[u8]);
+
+    // True once since the game last finished writing to battery-backed RAM.
+    fn take_save_request(&mut self) -> bool {
+        false
+    }
+
+    fn tick(&mut self, _cycles: u32) {}
+
+    fn rumble(&self) -> Option<bool> {
+        None
+    }
+}
+
+pub struct MbcCartridge {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mbc: Box<Mbc>,
+    // Set once RAM has been written to and then disabled again,
+    // which is how games signal they're done saving.
+    save_requested: bool,
+    ram_written: bool
+}
+
+impl MbcCartridge {
+    pub fn new(rom: Vec<u8>, mbc: Box<Mbc>) -> Self {
+        let ram_len = mbc.ram_size(ram_size(get_u8(&rom, 0x149)));
+        let mut rom = rom;
+        // Banks past the end of a short ROM read as 0xFF, like an open bus would.
+        let padded_len = ((rom.len() + ROM_BANK_SIZE - 1) / ROM_BANK_SIZE).max(2) * ROM_BANK_SIZE;
+        rom.resize(padded_len, 0xFF);
+        MbcCartridge {
+            rom: rom,
+            ram: vec![0x0; ram_len],
+            mbc: mbc,
+            save_requested: false,
+            ram_written: false
+        }
+    }
+
+    fn rom_offset(&self, bank: usize, addr: u16) -> usize {
+        // Bank numbers wrap around to fit the ROM, since unused high bits are ignored.
+        let bank = bank % (self.rom.len() / ROM_BANK_SIZE);
+        bank * ROM_BANK_SIZE + (addr as usize % ROM_BANK_SIZE)
+    }
+}
+
+impl Cartridge for MbcCartridge {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 {
+            self.mbc.rom_bank0()
+        } else {
+            self.mbc.rom_bank()
+        };
+        self.rom[self.rom_offset(bank, addr)]
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        let was_ram_enabled = self.mbc.is_ram_enabled();
+        self.mbc.write_control(addr, value);
+        if was_ram_enabled && !self.mbc.is_ram_enabled() && self.ram_written {
+            self.ram_written = false;
+            self.save_requested = true;
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.mbc.read_ram(&self.ram, addr)
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.mbc.is_ram_enabled() {
+            self.ram_written = true;
+        }
+        self.mbc.write_ram(&mut self.ram, addr, value);
+    }
+
+    fn save_ram(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend(self.mbc.save_extra());
+        data
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[.. len].copy_from_slice(&data[.. len]);
+        self.mbc.load_extra(&data[len ..]);
+    }
+
+    fn take_save_request(&mut self) -> bool {
+        let requested = self.save_requested;
+        self.save_requested = false;
+        requested
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.mbc.tick(cycles);
+    }
+
+    fn rumble(&self) -> Option<bool> {
+        self.mbc.rumble()
+    }
+}
+
+// Picks the cartridge hardware to emulate from the type byte at 0x147.
+pub fn new_cartridge(rom: Vec<u8>) -> CartridgeValidationResult<Box<Cartridge>> {
+    let cartridge_type = get_u8(&rom, 0x147);
+    let mbc: Box<Mbc> = match cartridge_type {
+        0x00 | 0x08 | 0x09 => Box::new(NoMbc),
+        0x01 ... 0x03 => Box::new(Mbc1::new()),
+        0x05 | 0x06 => Box::new(Mbc2::new()),
+        0x0F | 0x10 => Box::new(Mbc3::new(true)),
+        0x11 ... 0x13 => Box::new(Mbc3::new(false)),
+        0x19 ... 0x1B => Box::new(Mbc5::new(false)),
+        0x1C ... 0x1E => Box::new(Mbc5::new(true)),
+        _ if !CartridgeType::from_code(cartridge_type).is_known() => {
+            return Err(CartridgeValidationError::InvalidCartridgeType(cartridge_type))
+        },
+        _ => return Err(CartridgeValidationError::UnsupportedCartridgeType(cartridge_type))
+    };
+    Ok(Box::new(MbcCartridge::new(rom, mbc)))
+}
@@ -1,5 +1,24 @@
 use util::*;
 use mem::*;
+use interrupt::*;
+
+// Printing every instruction is far too slow to play with, so it's opt-in.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if cfg!(feature = "trace") {
+            println!($($arg)*);
+        }
+    }
+}
+
+// For the start of a trace line, which the instruction's trace! finishes.
+macro_rules! trace_prefix {
+    ($($arg:tt)*) => {
+        if cfg!(feature = "trace") {
+            print!($($arg)*);
+        }
+    }
+}
 
 pub struct Cpu {
     a: u8, f: u8,
@@ -9,6 +28,23 @@ pub struct Cpu {
     sp: u16,
     pc: u16,
 
+    // Total T-cycles (4.194304 MHz ticks) elapsed since power on.
+    cycles: u64,
+
+    // Interrupt master enable.
+    ime: bool,
+    // EI only takes effect after the instruction following it.
+    ime_scheduled: bool,
+
+    // Low-power states entered by HALT and STOP respectively.
+    halted: bool,
+    stopped: bool,
+    // Set when HALT is executed with IME off and an interrupt already pending,
+    // which causes the following byte to be read twice.
+    halt_bug: bool,
+    // Set by an illegal opcode. Nothing but a reset gets the CPU out of this.
+    locked: bool,
+
     mem: Mem
 }
 
@@ -64,28 +100,108 @@ enum Operand {
 }
 
 impl Cpu {
+    // There's no boot ROM, so this starts where the DMG's leaves off.
     pub fn new() -> Self {
         Cpu {
-            a: 0x0, f: 0x0,
-            b: 0x0, c: 0x0,
-            d: 0x0, e: 0x0,
-            h: 0x0, l: 0x0,
+            a: 0x01, f: 0xB0,
+            b: 0x00, c: 0x13,
+            d: 0x00, e: 0xD8,
+            h: 0x01, l: 0x4D,
             sp: 0xFFFE,
             pc: 0x100,
 
+            cycles: 0,
+
+            ime: false,
+            ime_scheduled: false,
+
+            halted: false,
+            stopped: false,
+            halt_bug: false,
+            locked: false,
+
             mem: Mem::new()
         }
     }
 
-    pub fn run(&mut self, rom: &[u8]) -> () {
-        println!("Beginning execution.");
+    pub fn mem(&self) -> &Mem {
+        &self.mem
+    }
+
+    pub fn mem_mut(&mut self) -> &mut Mem {
+        &mut self.mem
+    }
 
-        self.mem.load_fixed_rom_bank(&rom[0 .. 0x4000]);
+    // Executes a single instruction, returning how many T-cycles it took.
+    pub fn step(&mut self) -> u32 {
+        let cycles = if self.locked {
+            4
+        } else if self.stopped {
+            self.stopped_step()
+        } else {
+            match self.interrupt_dispatch() {
+                Some(cycles) => cycles,
+                None if self.halted => 4,
+                None => self.instruction_step()
+            }
+        };
+        self.cycles += cycles as u64;
+        self.mem.tick(cycles);
+        cycles
+    }
+
+    fn instruction_step(&mut self) -> u32 {
+        let ime_scheduled = self.ime_scheduled;
+        let opcode = self.mem.read_u8(self.pc);
+        if self.halt_bug {
+            // PC fails to advance past the opcode, so the instruction sees its own
+            // opcode as its first operand byte and single-byte ones run twice.
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
+        let cycles = self.opcode_exec(opcode);
+        if ime_scheduled && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
+        }
+        cycles
+    }
+
+    fn stopped_step(&mut self) -> u32 {
+        // Only a joypad press brings the CPU out of STOP.
+        if self.mem.is_interrupt_requested(Interrupt::Joypad) {
+            self.stopped = false;
+        }
+        4
+    }
 
-        loop {
-            let opcode = self.mem.read_u8(self.pc);
-            self.opcode_exec(opcode)
+    fn interrupt_dispatch(&mut self) -> Option<u32> {
+        let interrupt = match self.mem.pending_interrupt() {
+            Some(interrupt) => interrupt,
+            None => return None
+        };
+        // A pending interrupt always ends HALT, even when IME is off.
+        let wake_cycles = if self.halted {
+            self.halted = false;
+            4
+        } else {
+            0
+        };
+        if !self.ime {
+            return if wake_cycles > 0 { Some(wake_cycles) } else { None };
         }
+        trace!("[{:04X}] Interrupt {:?}", self.pc, interrupt);
+        self.ime = false;
+        self.mem.acknowledge_interrupt(interrupt);
+        let pc = self.pc;
+        self.push_stack_u16(pc);
+        self.pc = interrupt.vector();
+        // 2 wait states, 2 cycles for the push, and 1 for the jump.
+        Some(20 + wake_cycles)
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
     }
 
     fn reg8_string(&self, reg_name: Reg8Name) -> &str {
@@ -198,10 +314,50 @@ impl Cpu {
         }
     }
 
-    fn opcode_exec(&mut self, opcode: u8) {
-        print!("[{:04X}] {:02X} · ", self.pc, opcode);
+    fn operand8_string(&self, operand: Operand) -> String {
+        match operand {
+            Operand::Reg8(reg_name) => self.reg8_string(reg_name).to_string(),
+            Operand::HLAddr => "(HL)".to_string(),
+            Operand::Immediate8 => format!("{:02X}", self.mem.read_u8(self.pc+1)),
+            _ => unreachable!("Only Reg8, HLAddr, and Immediate8 are 8-bit operands.")
+        }
+    }
+
+    fn operand8_read(&mut self, operand: Operand) -> u8 {
+        match operand {
+            Operand::Reg8(reg_name) => self.reg8_read(reg_name),
+            Operand::HLAddr => self.read_hladdr_u8(),
+            Operand::Immediate8 => {
+                let value = self.mem.read_u8(self.pc+1);
+                self.pc += 1;
+                value
+            },
+            _ => unreachable!("Only Reg8, HLAddr, and Immediate8 are 8-bit operands.")
+        }
+    }
+
+    fn operand8_cycles(&self, operand: Operand) -> u32 {
+        match operand {
+            Operand::Reg8(_) => 4,
+            Operand::HLAddr | Operand::Immediate8 => 8,
+            _ => unreachable!("Only Reg8, HLAddr, and Immediate8 are 8-bit operands.")
+        }
+    }
+
+    fn operand8_write(&mut self, operand: Operand, value: u8) {
+        match operand {
+            Operand::Reg8(reg_name) => self.reg8_write(reg_name, value),
+            Operand::HLAddr => self.write_hladdr_u8(value),
+            _ => unreachable!("Only Reg8 and HLAddr are writable 8-bit operands.")
+        }
+    }
+
+    fn opcode_exec(&mut self, opcode: u8) -> u32 {
+        trace_prefix!("[{:04X}] {:02X} · ", self.pc, opcode);
         match opcode {
             0x00 => self.nop(),
+            0x76 => self.halt(),
+            0x10 => self.stop(),
 
             // RSTs
             0xC7 => self.rst(ResetCode::Rst00),
@@ -216,11 +372,17 @@ impl Cpu {
             // LDs
             0x02 => self.ld(Operand::RegAddr(Reg16Name::BC), Operand::Reg8(Reg8Name::A)),
             0x12 => self.ld(Operand::RegAddr(Reg16Name::DE), Operand::Reg8(Reg8Name::A)),
-            0x77 => self.ld(Operand::RegAddr(Reg16Name::HL), Operand::Reg8(Reg8Name::A)),
+            0x0A => self.ld(Operand::Reg8(Reg8Name::A), Operand::RegAddr(Reg16Name::BC)),
+            0x1A => self.ld(Operand::Reg8(Reg8Name::A), Operand::RegAddr(Reg16Name::DE)),
             0xEA => self.ld(Operand::Addr16, Operand::Reg8(Reg8Name::A)),
+            0xFA => self.ld(Operand::Reg8(Reg8Name::A), Operand::Addr16),
             0x06 => self.ld(Operand::Reg8(Reg8Name::B), Operand::Immediate8),
+            0x0E => self.ld(Operand::Reg8(Reg8Name::C), Operand::Immediate8),
             0x16 => self.ld(Operand::Reg8(Reg8Name::D), Operand::Immediate8),
+            0x1E => self.ld(Operand::Reg8(Reg8Name::E), Operand::Immediate8),
             0x26 => self.ld(Operand::Reg8(Reg8Name::H), Operand::Immediate8),
+            0x2E => self.ld(Operand::Reg8(Reg8Name::L), Operand::Immediate8),
+            0x3E => self.ld(Operand::Reg8(Reg8Name::A), Operand::Immediate8),
             0x7F => self.ld(Operand::Reg8(Reg8Name::A), Operand::Reg8(Reg8Name::A)),
             0x78 => self.ld(Operand::Reg8(Reg8Name::A), Operand::Reg8(Reg8Name::B)),
             0x79 => self.ld(Operand::Reg8(Reg8Name::A), Operand::Reg8(Reg8Name::C)),
@@ -236,6 +398,7 @@ impl Cpu {
             0x44 => self.ld(Operand::Reg8(Reg8Name::B), Operand::Reg8(Reg8Name::H)),
             0x45 => self.ld(Operand::Reg8(Reg8Name::B), Operand::Reg8(Reg8Name::L)),
             0x46 => self.ld(Operand::Reg8(Reg8Name::B), Operand::HLAddr),
+            0x47 => self.ld(Operand::Reg8(Reg8Name::B), Operand::Reg8(Reg8Name::A)),
             0x48 => self.ld(Operand::Reg8(Reg8Name::C), Operand::Reg8(Reg8Name::B)),
             0x49 => self.ld(Operand::Reg8(Reg8Name::C), Operand::Reg8(Reg8Name::C)),
             0x4A => self.ld(Operand::Reg8(Reg8Name::C), Operand::Reg8(Reg8Name::D)),
@@ -243,6 +406,7 @@ impl Cpu {
             0x4C => self.ld(Operand::Reg8(Reg8Name::C), Operand::Reg8(Reg8Name::H)),
             0x4D => self.ld(Operand::Reg8(Reg8Name::C), Operand::Reg8(Reg8Name::L)),
             0x4E => self.ld(Operand::Reg8(Reg8Name::C), Operand::HLAddr),
+            0x4F => self.ld(Operand::Reg8(Reg8Name::C), Operand::Reg8(Reg8Name::A)),
             0x50 => self.ld(Operand::Reg8(Reg8Name::D), Operand::Reg8(Reg8Name::B)),
             0x51 => self.ld(Operand::Reg8(Reg8Name::D), Operand::Reg8(Reg8Name::C)),
             0x52 => self.ld(Operand::Reg8(Reg8Name::D), Operand::Reg8(Reg8Name::D)),
@@ -250,6 +414,7 @@ impl Cpu {
             0x54 => self.ld(Operand::Reg8(Reg8Name::D), Operand::Reg8(Reg8Name::H)),
             0x55 => self.ld(Operand::Reg8(Reg8Name::D), Operand::Reg8(Reg8Name::L)),
             0x56 => self.ld(Operand::Reg8(Reg8Name::D), Operand::HLAddr),
+            0x57 => self.ld(Operand::Reg8(Reg8Name::D), Operand::Reg8(Reg8Name::A)),
             0x58 => self.ld(Operand::Reg8(Reg8Name::E), Operand::Reg8(Reg8Name::B)),
             0x59 => self.ld(Operand::Reg8(Reg8Name::E), Operand::Reg8(Reg8Name::C)),
             0x5A => self.ld(Operand::Reg8(Reg8Name::E), Operand::Reg8(Reg8Name::D)),
@@ -257,6 +422,7 @@ impl Cpu {
             0x5C => self.ld(Operand::Reg8(Reg8Name::E), Operand::Reg8(Reg8Name::H)),
             0x5D => self.ld(Operand::Reg8(Reg8Name::E), Operand::Reg8(Reg8Name::L)),
             0x5E => self.ld(Operand::Reg8(Reg8Name::E), Operand::HLAddr),
+            0x5F => self.ld(Operand::Reg8(Reg8Name::E), Operand::Reg8(Reg8Name::A)),
             0x60 => self.ld(Operand::Reg8(Reg8Name::H), Operand::Reg8(Reg8Name::B)),
             0x61 => self.ld(Operand::Reg8(Reg8Name::H), Operand::Reg8(Reg8Name::C)),
             0x62 => self.ld(Operand::Reg8(Reg8Name::H), Operand::Reg8(Reg8Name::D)),
@@ -264,6 +430,7 @@ impl Cpu {
             0x64 => self.ld(Operand::Reg8(Reg8Name::H), Operand::Reg8(Reg8Name::H)),
             0x65 => self.ld(Operand::Reg8(Reg8Name::H), Operand::Reg8(Reg8Name::L)),
             0x66 => self.ld(Operand::Reg8(Reg8Name::H), Operand::HLAddr),
+            0x67 => self.ld(Operand::Reg8(Reg8Name::H), Operand::Reg8(Reg8Name::A)),
             0x68 => self.ld(Operand::Reg8(Reg8Name::L), Operand::Reg8(Reg8Name::B)),
             0x69 => self.ld(Operand::Reg8(Reg8Name::L), Operand::Reg8(Reg8Name::C)),
             0x6A => self.ld(Operand::Reg8(Reg8Name::L), Operand::Reg8(Reg8Name::D)),
@@ -271,13 +438,50 @@ impl Cpu {
             0x6C => self.ld(Operand::Reg8(Reg8Name::L), Operand::Reg8(Reg8Name::H)),
             0x6D => self.ld(Operand::Reg8(Reg8Name::L), Operand::Reg8(Reg8Name::L)),
             0x6E => self.ld(Operand::Reg8(Reg8Name::L), Operand::HLAddr),
+            0x6F => self.ld(Operand::Reg8(Reg8Name::L), Operand::Reg8(Reg8Name::A)),
             0x70 => self.ld(Operand::HLAddr, Operand::Reg8(Reg8Name::B)),
             0x71 => self.ld(Operand::HLAddr, Operand::Reg8(Reg8Name::C)),
             0x72 => self.ld(Operand::HLAddr, Operand::Reg8(Reg8Name::D)),
             0x73 => self.ld(Operand::HLAddr, Operand::Reg8(Reg8Name::E)),
             0x74 => self.ld(Operand::HLAddr, Operand::Reg8(Reg8Name::H)),
             0x75 => self.ld(Operand::HLAddr, Operand::Reg8(Reg8Name::L)),
+            0x77 => self.ld(Operand::HLAddr, Operand::Reg8(Reg8Name::A)),
             0x36 => self.ld(Operand::HLAddr, Operand::Immediate8),
+            0x01 => self.ld(Operand::Reg16(Reg16Name::BC), Operand::Immediate16),
+            0x11 => self.ld(Operand::Reg16(Reg16Name::DE), Operand::Immediate16),
+            0x21 => self.ld(Operand::Reg16(Reg16Name::HL), Operand::Immediate16),
+            0x31 => self.ld(Operand::StackPointer, Operand::Immediate16),
+            0xF9 => self.ld(Operand::StackPointer, Operand::Reg16(Reg16Name::HL)),
+            0x08 => self.ld(Operand::Addr16, Operand::StackPointer),
+            0x22 => self.ldi_hl_a(),
+            0x2A => self.ldi_a_hl(),
+            0x32 => self.ldd_hl_a(),
+            0x3A => self.ldd_a_hl(),
+            0xE0 => self.ldh_a8_a(),
+            0xF0 => self.ldh_a_a8(),
+            0xE2 => self.ldh_c_a(),
+            0xF2 => self.ldh_a_c(),
+            0xF8 => self.ld_hl_sp_r8(),
+
+            // PUSHs
+            0xC5 => self.push(Reg16Name::BC),
+            0xD5 => self.push(Reg16Name::DE),
+            0xE5 => self.push(Reg16Name::HL),
+            0xF5 => self.push(Reg16Name::AF),
+
+            // POPs
+            0xC1 => self.pop(Reg16Name::BC),
+            0xD1 => self.pop(Reg16Name::DE),
+            0xE1 => self.pop(Reg16Name::HL),
+            0xF1 => self.pop(Reg16Name::AF),
+
+            // JPs
+            0xC3 => self.jp(None, Operand::Addr16),
+            0xC2 => self.jp(Some(Condition::NZ), Operand::Addr16),
+            0xCA => self.jp(Some(Condition::Z), Operand::Addr16),
+            0xD2 => self.jp(Some(Condition::NC), Operand::Addr16),
+            0xDA => self.jp(Some(Condition::C), Operand::Addr16),
+            0xE9 => self.jp(None, Operand::Reg16(Reg16Name::HL)),
 
             // JRs
             0x18 => self.jr(None, Operand::Immediate8),
@@ -286,6 +490,13 @@ impl Cpu {
             0x30 => self.jr(Some(Condition::NC), Operand::Immediate8),
             0x38 => self.jr(Some(Condition::C), Operand::Immediate8),
 
+            // CALLs
+            0xCD => self.call(None),
+            0xC4 => self.call(Some(Condition::NZ)),
+            0xCC => self.call(Some(Condition::Z)),
+            0xD4 => self.call(Some(Condition::NC)),
+            0xDC => self.call(Some(Condition::C)),
+
             // RETs
             0xC9 => self.ret(None),
             0xC0 => self.ret(Some(Condition::NZ)),
@@ -294,6 +505,18 @@ impl Cpu {
             0xD8 => self.ret(Some(Condition::C)),
 
             // INCs
+            0x04 => self.inc(Operand::Reg8(Reg8Name::B)),
+            0x14 => self.inc(Operand::Reg8(Reg8Name::D)),
+            0x24 => self.inc(Operand::Reg8(Reg8Name::H)),
+            0x34 => self.inc(Operand::HLAddr),
+            0x03 => self.inc(Operand::Reg16(Reg16Name::BC)),
+            0x13 => self.inc(Operand::Reg16(Reg16Name::DE)),
+            0x23 => self.inc(Operand::Reg16(Reg16Name::HL)),
+            0x33 => self.inc(Operand::StackPointer),
+            0x0C => self.inc(Operand::Reg8(Reg8Name::C)),
+            0x1C => self.inc(Operand::Reg8(Reg8Name::E)),
+            0x2C => self.inc(Operand::Reg8(Reg8Name::L)),
+            0x3C => self.inc(Operand::Reg8(Reg8Name::A)),
 
             // DECs
             0x05 => self.dec(Operand::Reg8(Reg8Name::B)),
@@ -319,6 +542,22 @@ impl Cpu {
             0x86 => self.add(Operand::HLAddr),
             0x87 => self.add(Operand::Reg8(Reg8Name::A)),
             0xC6 => self.add(Operand::Immediate8),
+            0x09 => self.add_hl(Operand::Reg16(Reg16Name::BC)),
+            0x19 => self.add_hl(Operand::Reg16(Reg16Name::DE)),
+            0x29 => self.add_hl(Operand::Reg16(Reg16Name::HL)),
+            0x39 => self.add_hl(Operand::StackPointer),
+            0xE8 => self.add_sp_r8(),
+
+            // ADCs
+            0x88 => self.adc(Operand::Reg8(Reg8Name::B)),
+            0x89 => self.adc(Operand::Reg8(Reg8Name::C)),
+            0x8A => self.adc(Operand::Reg8(Reg8Name::D)),
+            0x8B => self.adc(Operand::Reg8(Reg8Name::E)),
+            0x8C => self.adc(Operand::Reg8(Reg8Name::H)),
+            0x8D => self.adc(Operand::Reg8(Reg8Name::L)),
+            0x8E => self.adc(Operand::HLAddr),
+            0x8F => self.adc(Operand::Reg8(Reg8Name::A)),
+            0xCE => self.adc(Operand::Immediate8),
 
             // SUBs
             0x90 => self.sub(Operand::Reg8(Reg8Name::B)),
@@ -331,6 +570,17 @@ impl Cpu {
             0x97 => self.sub(Operand::Reg8(Reg8Name::A)),
             0xD6 => self.sub(Operand::Immediate8),
 
+            // SBCs
+            0x98 => self.sbc(Operand::Reg8(Reg8Name::B)),
+            0x99 => self.sbc(Operand::Reg8(Reg8Name::C)),
+            0x9A => self.sbc(Operand::Reg8(Reg8Name::D)),
+            0x9B => self.sbc(Operand::Reg8(Reg8Name::E)),
+            0x9C => self.sbc(Operand::Reg8(Reg8Name::H)),
+            0x9D => self.sbc(Operand::Reg8(Reg8Name::L)),
+            0x9E => self.sbc(Operand::HLAddr),
+            0x9F => self.sbc(Operand::Reg8(Reg8Name::A)),
+            0xDE => self.sbc(Operand::Immediate8),
+
             // ANDs
             0xA0 => self.and(Operand::Reg8(Reg8Name::B)),
             0xA1 => self.and(Operand::Reg8(Reg8Name::C)),
@@ -375,66 +625,118 @@ impl Cpu {
             0xBF => self.cp(Operand::Reg8(Reg8Name::A)),
             0xFE => self.cp(Operand::Immediate8),
 
-            0xC3 => self.jump_nn(),
-            0x3E => self.ld_a_d8(),
-            0xF3 => self.di(),
+            // Accumulator rotates
+            0x07 => self.rlca(),
             0x0F => self.rrca(),
-            0xE0 => self.ldh_a8_a(),
-            0xCD => self.call(),
-            0xF0 => self.ldh_a_a8(),
-            0x47 => self.ld_b_a(),
-            0x31 => self.ld_sp_d16(),
-            0x21 => self.ld_hl_d16(),
-            0x01 => self.ld_bc_d16(),
-            0x23 => self.inc_hl(),
-            0x04 => self.inc_b(),
-            0x22 => self.ldi_hl_a(),
-            0xE5 => self.push_hl(),
-            0xD5 => self.push_de(),
-            0xC5 => self.push_bc(),
-            0xFA => self.ld_a_a16(),
+            0x17 => self.rla(),
+            0x1F => self.rra(),
+
+            // Misc. flag and accumulator ops
+            0x27 => self.daa(),
+            0x2F => self.cpl(),
+            0x37 => self.scf(),
+            0x3F => self.ccf(),
+
+            0xF3 => self.di(),
+            0xFB => self.ei(),
+            0xD9 => self.reti(),
             0xCB => {
                 let opcode = self.mem.read_u8(self.pc+1);
-                print!("{:02X} ", opcode);
-                match opcode {
-                    0x87 => self.cb_res_0_a(),
-                    _ => {
-                        println!("Unimplemented");
-                        self.mem.dump();
-                        panic!()
-                    }
-                }
+                trace_prefix!("{:02X} ", opcode);
+                self.cb_opcode_exec(opcode)
             },
-            _ => {
-                println!("Unimplemented");
-                self.mem.dump();
-                panic!()
+            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+                // These lock up a real CPU, which then ignores even interrupts.
+                trace!("Illegal opcode");
+                self.locked = true;
+                4
             }
         }
     }
 
+    fn cb_operand(&self, index: u8) -> Operand {
+        match index & 0b0000_0111u8 {
+            0 => Operand::Reg8(Reg8Name::B),
+            1 => Operand::Reg8(Reg8Name::C),
+            2 => Operand::Reg8(Reg8Name::D),
+            3 => Operand::Reg8(Reg8Name::E),
+            4 => Operand::Reg8(Reg8Name::H),
+            5 => Operand::Reg8(Reg8Name::L),
+            6 => Operand::HLAddr,
+            7 => Operand::Reg8(Reg8Name::A),
+            _ => unreachable!()
+        }
+    }
+
+    fn cb_cycles(&self, operand: Operand) -> u32 {
+        match operand {
+            Operand::HLAddr => 16,
+            _ => 8
+        }
+    }
+
+    // The CB table is completely regular: the low 3 bits select the operand,
+    // and the upper bits select the operation (and bit index, for BIT/RES/SET).
+    fn cb_opcode_exec(&mut self, opcode: u8) -> u32 {
+        let operand = self.cb_operand(opcode);
+        let bit = (opcode >> 3) & 0b0000_0111u8;
+        match opcode >> 6 {
+            0b00 => match bit {
+                0 => self.rlc(operand),
+                1 => self.rrc(operand),
+                2 => self.rl(operand),
+                3 => self.rr(operand),
+                4 => self.sla(operand),
+                5 => self.sra(operand),
+                6 => self.swap(operand),
+                7 => self.srl(operand),
+                _ => unreachable!()
+            },
+            0b01 => self.bit(bit, operand),
+            0b10 => self.res(bit, operand),
+            0b11 => self.set(bit, operand),
+            _ => unreachable!()
+        }
+    }
+
     fn push_stack_u8(&mut self, value: u8) {
-        self.sp -= 1;
+        self.sp = self.sp.wrapping_sub(1);
         self.mem.write_u8(self.sp, value);
     }
 
     fn push_stack_u16(&mut self, value: u16) {
-        self.sp -= 2;
+        self.sp = self.sp.wrapping_sub(2);
         self.mem.write_u16(self.sp, value);
     }
 
     fn pop_stack_u8(&mut self) -> u8 {
         let popped = self.mem.read_u8(self.sp);
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
         popped
     }
 
     fn pop_stack_u16(&mut self) -> u16 {
-        let popped = switch_u16(self.mem.read_u16(self.sp));
-        self.sp += 2;
+        let popped = self.mem.read_u16(self.sp);
+        self.sp = self.sp.wrapping_add(2);
         popped
     }
 
+    fn flags_write(&mut self, zero: bool, subtraction: bool, halfcarry: bool, carry: bool) {
+        self.f = 0;
+        if zero {
+            self.set_f_zero();
+        }
+        if subtraction {
+            self.set_f_subtraction();
+        }
+        if halfcarry {
+            self.set_f_halfcarry();
+        }
+        if carry {
+            self.set_f_carry();
+        }
+    }
+
     fn is_f_zero(&self) -> bool {
         (0b1000_0000u8 & self.f) == 0b1000_0000u8
     }
@@ -444,11 +746,11 @@ impl Cpu {
     }
 
     fn reset_f_zero(&mut self) {
-        self.f ^= 0b1000_0000u8
+        self.f &= !0b1000_0000u8
     }
 
     fn is_f_subtraction(&self) -> bool {
-        (0b01000_000u8 & self.f) == 0b0100_0000u8
+        (0b0100_0000u8 & self.f) == 0b0100_0000u8
     }
 
     fn set_f_subtraction(&mut self) {
@@ -456,7 +758,7 @@ impl Cpu {
     }
 
     fn reset_f_subtraction(&mut self) {
-        self.f ^= 0b0100_0000u8
+        self.f &= !0b0100_0000u8
     }
 
     fn is_f_halfcarry(&self) -> bool {
@@ -468,7 +770,7 @@ impl Cpu {
     }
 
     fn reset_f_halfcarry(&mut self) {
-        self.f ^= 0b0010_0000u8
+        self.f &= !0b0010_0000u8
     }
 
     fn is_f_carry(&self) -> bool {
@@ -480,17 +782,18 @@ impl Cpu {
     }
 
     fn reset_f_carry(&mut self) {
-        self.f ^= 0b0001_0000u8
+        self.f &= !0b0001_0000u8
     }
 
-    fn nop(&mut self) {
+    fn nop(&mut self) -> u32 {
         self.pc += 1;
-        println!("NOP");
+        trace!("NOP");
+        4
     }
 
-    fn rst(&mut self, reset_code: ResetCode) {
-        let pc = self.pc;
-        self.push_stack_u16(pc);
+    fn rst(&mut self, reset_code: ResetCode) -> u32 {
+        let next = self.pc+1;
+        self.push_stack_u16(next);
         let dest = match reset_code {
             ResetCode::Rst00 => 0x00,
             ResetCode::Rst08 => 0x08,
@@ -501,56 +804,130 @@ impl Cpu {
             ResetCode::Rst30 => 0x30,
             ResetCode::Rst38 => 0x38
         };
-        if self.pc == dest {
-            self.mem.dump();
-            panic!("Reset loop. Implement more I/O stuff.");
-        }
         self.pc = dest;
-        println!("RST {:02X}", self.pc);
+        trace!("RST {:02X}", self.pc);
+        16
     }
 
-    fn jump_nn(&mut self) {
-        self.pc = self.mem.read_u16(self.pc+1);
-        println!("JP {:02X}", self.pc);
+    fn jp(&mut self, condition: Option<Condition>, operand: Operand) -> u32 {
+        match operand {
+            Operand::Addr16 => {
+                let addr = self.mem.read_u16(self.pc+1);
+                let satisfied = match condition {
+                    Some(cond) => {
+                        trace!("JP {},{:04X}", self.cond_string(cond), addr);
+                        self.cond_eval(cond)
+                    }
+                    None => {
+                        trace!("JP {:04X}", addr);
+                        true
+                    }
+                };
+
+                if satisfied {
+                    self.pc = addr;
+                    16
+                } else {
+                    self.pc += 3;
+                    12
+                }
+            },
+            Operand::Reg16(Reg16Name::HL) => {
+                trace!("JP (HL)");
+                self.pc = self.reg16_read(Reg16Name::HL);
+                4
+            },
+            _ => unreachable!("JP only supports Addr16 and HL.")
+        }
     }
 
-    fn ld(&mut self, r1: Operand, r2: Operand) {
-        match r1 {
+    fn ld(&mut self, r1: Operand, r2: Operand) -> u32 {
+        let cycles = match r1 {
             Operand::Reg8(reg_name) => {
                 match r2 {
                     Operand::Reg8(second_reg_name) => {
-                        println!("LD {},{}",
+                        trace!("LD {},{}",
                             self.reg8_string(reg_name), self.reg8_string(second_reg_name));
                         let value = self.reg8_read(second_reg_name);
                         self.reg8_write(reg_name, value);
+                        4
                     },
                     Operand::HLAddr => {
-                        println!("LD {},(HL)", self.reg8_string(reg_name));
+                        trace!("LD {},(HL)", self.reg8_string(reg_name));
                         let value = self.read_hladdr_u8();
                         self.reg8_write(reg_name, value);
+                        8
                     },
                     Operand::Immediate8 => {
                         let value = self.mem.read_u8(self.pc+1);
-                        println!("LD {},{:02X}", self.reg8_string(reg_name), value);
+                        trace!("LD {},{:02X}", self.reg8_string(reg_name), value);
                         self.reg8_write(reg_name, value);
                         self.pc += 1;
+                        8
                     },
-                    _ => unreachable!("LD {} only supports Reg8, HLAddr, and Immediate8.",
+                    Operand::RegAddr(second_reg_name) => {
+                        trace!("LD {},({})",
+                            self.reg8_string(reg_name), self.reg16_string(second_reg_name));
+                        let value = self.reg16addr_read_u8(second_reg_name);
+                        self.reg8_write(reg_name, value);
+                        8
+                    },
+                    Operand::Addr16 => {
+                        let addr = self.mem.read_u16(self.pc+1);
+                        trace!("LD {},({:04X})", self.reg8_string(reg_name), addr);
+                        let value = self.mem.read_u8(addr);
+                        self.reg8_write(reg_name, value);
+                        self.pc += 2;
+                        16
+                    },
+                    _ => unreachable!("LD {} only supports Reg8, HLAddr, Immediate8, RegAddr, and Addr16.",
                         self.reg8_string(reg_name))
                 }
             },
+            Operand::Reg16(reg_name) => {
+                match r2 {
+                    Operand::Immediate16 => {
+                        let value = self.mem.read_u16(self.pc+1);
+                        trace!("LD {},{:04X}", self.reg16_string(reg_name), value);
+                        self.reg16_write(reg_name, value);
+                        self.pc += 2;
+                        12
+                    },
+                    _ => unreachable!("LD {} only supports Immediate16.",
+                        self.reg16_string(reg_name))
+                }
+            },
+            Operand::StackPointer => {
+                match r2 {
+                    Operand::Immediate16 => {
+                        let value = self.mem.read_u16(self.pc+1);
+                        trace!("LD SP,{:04X}", value);
+                        self.sp = value;
+                        self.pc += 2;
+                        12
+                    },
+                    Operand::Reg16(Reg16Name::HL) => {
+                        trace!("LD SP,HL");
+                        self.sp = self.reg16_read(Reg16Name::HL);
+                        8
+                    },
+                    _ => unreachable!("LD SP only supports Immediate16 and HL.")
+                }
+            },
             Operand::HLAddr => {
                 match r2 {
                     Operand::Reg8(reg_name) => {
-                        println!("LD (HL),{}", self.reg8_string(reg_name));
+                        trace!("LD (HL),{}", self.reg8_string(reg_name));
                         let value = self.reg8_read(reg_name);
                         self.write_hladdr_u8(value);
+                        8
                     },
                     Operand::Immediate8 => {
                         let value = self.mem.read_u8(self.pc+1);
-                        println!("LD (HL),{:02X}", value);
+                        trace!("LD (HL),{:02X}", value);
                         self.write_hladdr_u8(value);
                         self.pc += 1;
+                        12
                     },
                     _ => unreachable!("LD (HL) only supports Reg8 and Immediate8.")
                 }
@@ -558,10 +935,11 @@ impl Cpu {
             Operand::RegAddr(reg_name) => {
                 match r2 {
                     Operand::Reg8(second_reg_name) => {
-                        println!("LD ({}),{}",
+                        trace!("LD ({}),{}",
                             self.reg16_string(reg_name), self.reg8_string(second_reg_name));
                         let value = self.reg8_read(second_reg_name);
                         self.reg16addr_write_u8(reg_name, value);
+                        8
                     },
                     _ => unreachable!("LD ({}) only supports Reg8.",
                         self.reg16_string(reg_name))
@@ -569,122 +947,272 @@ impl Cpu {
             },
             Operand::Addr16 => {
                 let addr = self.mem.read_u16(self.pc+1);
+                self.pc += 2;
                 match r2 {
                     Operand::Reg8(reg_name) => {
-                        println!("LD ({:02X}),{}", addr, self.reg8_string(reg_name));
+                        trace!("LD ({:04X}),{}", addr, self.reg8_string(reg_name));
                         let value = self.reg8_read(reg_name);
                         self.mem.write_u8(addr, value);
+                        16
+                    },
+                    Operand::StackPointer => {
+                        trace!("LD ({:04X}),SP", addr);
+                        let value = self.sp;
+                        self.mem.write_u16(addr, value);
+                        20
                     },
-                    _ => unreachable!("LD ({:02X}) only supports Reg8.", addr)
+                    _ => unreachable!("LD ({:04X}) only supports Reg8 and StackPointer.", addr)
                 }
-                self.pc += 2;
             },
-            _ => unreachable!("LD only supports Reg8, HLAddr, and RegAddr.")
+            _ => unreachable!("LD only supports Reg8, Reg16, StackPointer, HLAddr, RegAddr, and Addr16.")
         };
         self.pc += 1;
+        cycles
     }
 
-    fn ld_a_d8(&mut self) {
-        let operand = self.mem.read_u8(self.pc+1);
-        self.a = operand;
+    fn di(&mut self) -> u32 {
+        self.ime = false;
+        self.ime_scheduled = false;
+        self.pc += 1;
+        trace!("DI");
+        4
+    }
+
+    fn halt(&mut self) -> u32 {
+        trace!("HALT");
+        self.pc += 1;
+        if !self.ime && self.mem.pending_interrupt().is_some() {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+        4
+    }
+
+    fn stop(&mut self) -> u32 {
+        trace!("STOP");
+        // STOP is followed by a padding byte that's skipped over.
         self.pc += 2;
-        println!("LD A,{:02X}", operand);
+        self.mem.reset_div();
+        if self.mem.is_speed_switch_armed() {
+            // On CGB, STOP with KEY1 armed switches speed instead of stopping.
+            self.mem.switch_speed();
+        } else {
+            self.stopped = true;
+        }
+        4
     }
 
-    fn di(&mut self) {
-        // Disable interrupts
+    fn ei(&mut self) -> u32 {
+        if !self.ime {
+            self.ime_scheduled = true;
+        }
         self.pc += 1;
-        println!("DI");
+        trace!("EI");
+        4
     }
 
-    fn rrca(&mut self) {
-        self.f = self.a & 0b0000_0001u8;
-        self.a >>= 1;
-        if self.a == 0x0 {
-            self.set_f_zero();
+    fn reti(&mut self) -> u32 {
+        trace!("RETI");
+        self.pc = self.pop_stack_u16();
+        // Unlike EI, this takes effect immediately.
+        self.ime = true;
+        self.ime_scheduled = false;
+        16
+    }
+
+    fn rlca(&mut self) -> u32 {
+        let carry = self.a & 0b1000_0000u8 != 0;
+        self.a = self.a.rotate_left(1);
+        self.flags_write(false, false, false, carry);
+        self.pc += 1;
+        trace!("RLCA");
+        4
+    }
+
+    fn rrca(&mut self) -> u32 {
+        let carry = self.a & 0b0000_0001u8 != 0;
+        self.a = self.a.rotate_right(1);
+        self.flags_write(false, false, false, carry);
+        self.pc += 1;
+        trace!("RRCA");
+        4
+    }
+
+    fn rla(&mut self) -> u32 {
+        let carry = self.a & 0b1000_0000u8 != 0;
+        self.a = (self.a << 1) | self.is_f_carry() as u8;
+        self.flags_write(false, false, false, carry);
+        self.pc += 1;
+        trace!("RLA");
+        4
+    }
+
+    fn rra(&mut self) -> u32 {
+        let carry = self.a & 0b0000_0001u8 != 0;
+        self.a = (self.a >> 1) | ((self.is_f_carry() as u8) << 7);
+        self.flags_write(false, false, false, carry);
+        self.pc += 1;
+        trace!("RRA");
+        4
+    }
+
+    fn daa(&mut self) -> u32 {
+        trace!("DAA");
+        let mut carry = self.is_f_carry();
+        let mut adjust = 0;
+        if self.is_f_subtraction() {
+            if carry {
+                adjust |= 0x60;
+            }
+            if self.is_f_halfcarry() {
+                adjust |= 0x06;
+            }
+            self.a = self.a.wrapping_sub(adjust);
+        } else {
+            if carry || self.a > 0x99 {
+                adjust |= 0x60;
+                carry = true;
+            }
+            if self.is_f_halfcarry() || (self.a & 0xF) > 0x9 {
+                adjust |= 0x06;
+            }
+            self.a = self.a.wrapping_add(adjust);
+        }
+        let (zero, subtraction) = (self.a == 0, self.is_f_subtraction());
+        self.flags_write(zero, subtraction, false, carry);
+        self.pc += 1;
+        4
+    }
+
+    fn cpl(&mut self) -> u32 {
+        trace!("CPL");
+        self.a = !self.a;
+        self.set_f_subtraction();
+        self.set_f_halfcarry();
+        self.pc += 1;
+        4
+    }
+
+    fn scf(&mut self) -> u32 {
+        trace!("SCF");
+        self.reset_f_subtraction();
+        self.reset_f_halfcarry();
+        self.set_f_carry();
+        self.pc += 1;
+        4
+    }
+
+    fn ccf(&mut self) -> u32 {
+        trace!("CCF");
+        self.reset_f_subtraction();
+        self.reset_f_halfcarry();
+        if self.is_f_carry() {
+            self.reset_f_carry();
+        } else {
+            self.set_f_carry();
         }
         self.pc += 1;
-        println!("RRCA");
+        4
     }
 
-    fn ldh_a8_a(&mut self) {
+    fn ldh_a8_a(&mut self) -> u32 {
         let operand = self.mem.read_u8(self.pc+1);
         let addr = 0xFF00 + operand as u16;
         self.mem.write_u8(addr, self.a);
         self.pc += 2;
-        println!("LDH {:02X},A", operand);
+        trace!("LDH ({:02X}),A", operand);
+        12
     }
 
-    fn call(&mut self) {
-        let addr = self.mem.read_u16(self.pc+1);
-        let next = self.pc+3;
-        self.push_stack_u16(next);
-        self.pc = addr;
-        println!("CALL {:02X}", addr);
-    }
-
-    fn ldh_a_a8(&mut self) {
+    fn ldh_a_a8(&mut self) -> u32 {
         let operand = self.mem.read_u8(self.pc+1);
         let addr = 0xFF00 + operand as u16;
-        println!("LDH A,{:02X}", addr);
+        trace!("LDH A,({:02X})", operand);
         self.a = self.mem.read_u8(addr);
         self.pc += 2;
+        12
     }
 
-    fn ld_b_a(&mut self) {
-        self.b = self.a;
+    fn ldh_c_a(&mut self) -> u32 {
+        trace!("LD (C),A");
+        let addr = 0xFF00 + self.c as u16;
+        self.mem.write_u8(addr, self.a);
         self.pc += 1;
-        println!("LD B,A");
+        8
     }
 
-    fn cb_res_0_a(&mut self) {
-        self.a ^= 0b0000_0001u8;
-        self.pc += 2;
-        println!("RES 0,A");
+    fn ldh_a_c(&mut self) -> u32 {
+        trace!("LD A,(C)");
+        let addr = 0xFF00 + self.c as u16;
+        self.a = self.mem.read_u8(addr);
+        self.pc += 1;
+        8
     }
 
-    fn jr(&mut self, condition: Option<Condition>, operand: Operand) {
-        match operand {
+    fn call(&mut self, condition: Option<Condition>) -> u32 {
+        let addr = self.mem.read_u16(self.pc+1);
+        let satisfied = match condition {
+            Some(cond) => {
+                trace!("CALL {},{:04X}", self.cond_string(cond), addr);
+                self.cond_eval(cond)
+            }
+            None => {
+                trace!("CALL {:04X}", addr);
+                true
+            }
+        };
+        let next = self.pc+3;
+        if satisfied {
+            self.push_stack_u16(next);
+            self.pc = addr;
+            24
+        } else {
+            self.pc = next;
+            12
+        }
+    }
+
+    fn jr(&mut self, condition: Option<Condition>, operand: Operand) -> u32 {
+        let taken = match operand {
             Operand::Immediate8 => {
                 let value = self.mem.read_u8(self.pc+1) as i8;
                 let satisfied = match condition {
                     Some(cond) => {
-                        println!("JR {},{:02X}", self.cond_string(cond), value);
+                        trace!("JR {},{:02X}", self.cond_string(cond), value);
                         self.cond_eval(cond)
                     }
                     None => {
-                        println!("JR {:02X}", value);
+                        trace!("JR {:02X}", value);
                         true
                     }
                 };
 
+                // The offset is relative to the end of the instruction.
+                self.pc += 2;
                 if satisfied {
-                    if value > 0 {
-                        self.pc += value as u16;
-                    } else {
-                        self.pc -= (-value) as u16;
-                    }
-                } else {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(value as u16);
                 }
+                satisfied
             },
             _ => unreachable!("JR only supports Immediate8.")
         };
 
-        // Temp hack that prevents endless looping while waiting for vblank
-        if self.pc == 105 {
-            panic!();
+        if taken {
+            12
+        } else {
+            8
         }
     }
 
-    fn ret(&mut self, condition: Option<Condition>) {
+    fn ret(&mut self, condition: Option<Condition>) -> u32 {
         let satisfied = match condition {
             Some(cond) => {
-                println!("RET {}", self.cond_string(cond));
+                trace!("RET {}", self.cond_string(cond));
                 self.cond_eval(cond)
             }
             None => {
-                println!("RET");
+                trace!("RET");
                 true
             }
         };
@@ -693,330 +1221,591 @@ impl Cpu {
         } else {
             self.pc += 1;
         }
+        // A conditional RET spends an extra cycle evaluating its condition.
+        match (condition, satisfied) {
+            (None, _) => 16,
+            (Some(_), true) => 20,
+            (Some(_), false) => 8
+        }
     }
 
-    fn ld_sp_d16(&mut self) {
-        let operand = self.mem.read_u16(self.pc+1);
-        println!("LD SP,{:02X}", operand);
-        self.sp = operand;
-        self.pc += 3;
-    }
-
-    fn ld_hl_d16(&mut self) {
-        let operand = self.mem.read_u16(self.pc+1);
-        println!("LD HL,{:02X}", operand);
-        let bytes = u16_to_2u8s(operand);
-        self.h = bytes.0;
-        self.l = bytes.1;
-        self.pc += 3;
-    }
-
-    fn ld_bc_d16(&mut self) {
-        let operand = self.mem.read_u16(self.pc+1);
-        println!("LD BC,{:02X}", operand);
-        let bytes = u16_to_2u8s(operand);
-        self.b = bytes.0;
-        self.c = bytes.1;
-        self.pc += 3;
-    }
-
-    fn inc_hl(&mut self) {
-        println!("INC HL");
-        let value = u16_from_2u8s((self.l, self.h)) + 1;
-        let bytes = u16_to_2u8s(value);
-        self.h = bytes.0;
-        self.l = bytes.1;
-        self.pc += 1;
-    }
-
-    fn dec(&mut self, operand: Operand) {
-        match operand {
-            Operand::Reg8(reg_name) => {
-                println!("DEC {}", self.reg8_string(reg_name));
-                let value = self.reg8_read(reg_name).saturating_sub(1);
-                self.reg8_write(reg_name, value);
-                if value == 0 {
-                    self.set_f_zero();
-                    // I'm not sure how to interpret "Set if no borrow from bit 4."
-                    self.set_f_halfcarry();
+    fn inc(&mut self, operand: Operand) -> u32 {
+        let cycles = match operand {
+            Operand::Reg8(_) | Operand::HLAddr => {
+                trace!("INC {}", self.operand8_string(operand));
+                let orig = self.operand8_read(operand);
+                let value = orig.wrapping_add(1);
+                self.operand8_write(operand, value);
+                let carry = self.is_f_carry();
+                self.flags_write(value == 0, false, (orig & 0xF) == 0xF, carry);
+                match operand {
+                    Operand::HLAddr => 12,
+                    _ => 4
                 }
-                self.set_f_subtraction();
             },
             Operand::Reg16(reg_name) => {
-                println!("DEC {}", self.reg16_string(reg_name));
-                let value = self.reg16_read(reg_name).saturating_sub(1);
+                trace!("INC {}", self.reg16_string(reg_name));
+                let value = self.reg16_read(reg_name).wrapping_add(1);
                 self.reg16_write(reg_name, value);
+                8
             },
-            Operand::HLAddr => {
-                println!("DEC (HL)");
-                let value = self.read_hladdr_u8().saturating_sub(1);
-                self.write_hladdr_u8(value);
-                if value == 0 {
-                    self.set_f_zero();
-                    self.set_f_halfcarry();
+            Operand::StackPointer => {
+                trace!("INC SP");
+                self.sp = self.sp.wrapping_add(1);
+                8
+            },
+            _ => unreachable!("INC only supports Reg8, Reg16, HLAddr, and StackPointer.")
+        };
+        self.pc += 1;
+        cycles
+    }
+
+    fn dec(&mut self, operand: Operand) -> u32 {
+        let cycles = match operand {
+            Operand::Reg8(_) | Operand::HLAddr => {
+                trace!("DEC {}", self.operand8_string(operand));
+                let orig = self.operand8_read(operand);
+                let value = orig.wrapping_sub(1);
+                self.operand8_write(operand, value);
+                let carry = self.is_f_carry();
+                self.flags_write(value == 0, true, (orig & 0xF) == 0x0, carry);
+                match operand {
+                    Operand::HLAddr => 12,
+                    _ => 4
                 }
-                self.set_f_subtraction();
+            },
+            Operand::Reg16(reg_name) => {
+                trace!("DEC {}", self.reg16_string(reg_name));
+                let value = self.reg16_read(reg_name).wrapping_sub(1);
+                self.reg16_write(reg_name, value);
+                8
             },
             Operand::StackPointer => {
-                println!("DEC SP");
-                let value = self.sp.saturating_sub(1);
-                self.sp = value;
+                trace!("DEC SP");
+                self.sp = self.sp.wrapping_sub(1);
+                8
             },
             _ => unreachable!("DEC only supports Reg8, Reg16, HLAddr, and StackPointer.")
         };
         self.pc += 1;
+        cycles
     }
 
-    fn inc_b(&mut self) {
-        println!("INC B");
-        self.b.saturating_add(1);
-        if self.b == 0 {
-            self.set_f_zero();
-            // This might not be the correct interpretation of "Set if carry from bit 3."
-            self.set_f_halfcarry();
-        }
-        self.reset_f_subtraction();
-        self.pc += 1;
+    fn inc_hl_(&mut self) {
+        let value = self.reg16_read(Reg16Name::HL).wrapping_add(1);
+        self.reg16_write(Reg16Name::HL, value);
     }
 
-    fn inc_hl_(&mut self) {
-        let value = u16_from_2u8s((self.l, self.h)) + 1;
-        let bytes = u16_to_2u8s(value);
-        self.h = bytes.0;
-        self.l = bytes.1;
+    fn dec_hl_(&mut self) {
+        let value = self.reg16_read(Reg16Name::HL).wrapping_sub(1);
+        self.reg16_write(Reg16Name::HL, value);
     }
 
-    fn ldi_hl_a(&mut self) {
-        println!("LDI (HL),A");
+    fn ldi_hl_a(&mut self) -> u32 {
+        trace!("LDI (HL),A");
         let value = self.a;
         self.write_hladdr_u8(value);
         self.inc_hl_();
         self.pc += 1;
+        8
     }
 
-    fn push_hl(&mut self) {
-        println!("PUSH HL");
-        let value = u16_from_2u8s((self.l, self.h));
-        self.push_stack_u16(value);
+    fn ldi_a_hl(&mut self) -> u32 {
+        trace!("LDI A,(HL)");
+        self.a = self.read_hladdr_u8();
+        self.inc_hl_();
         self.pc += 1;
+        8
     }
 
-    fn push_de(&mut self) {
-        println!("PUSH HL");
-        let value = u16_from_2u8s((self.e, self.d));
-        self.push_stack_u16(value);
+    fn ldd_hl_a(&mut self) -> u32 {
+        trace!("LDD (HL),A");
+        let value = self.a;
+        self.write_hladdr_u8(value);
+        self.dec_hl_();
         self.pc += 1;
+        8
     }
 
-    fn push_bc(&mut self) {
-        println!("PUSH HL");
-        let value = u16_from_2u8s((self.c, self.b));
+    fn ldd_a_hl(&mut self) -> u32 {
+        trace!("LDD A,(HL)");
+        self.a = self.read_hladdr_u8();
+        self.dec_hl_();
+        self.pc += 1;
+        8
+    }
+
+    fn push(&mut self, reg_name: Reg16Name) -> u32 {
+        trace!("PUSH {}", self.reg16_string(reg_name));
+        let value = self.reg16_read(reg_name);
         self.push_stack_u16(value);
         self.pc += 1;
+        16
     }
 
-    fn ld_a_a16(&mut self) {
-        let addr = self.mem.read_u16(self.pc+1);
-        println!("LD A,({:02X})", addr);
-        self.a = self.mem.read_u8(addr);
-        self.pc += 3;
+    fn pop(&mut self, reg_name: Reg16Name) -> u32 {
+        trace!("POP {}", self.reg16_string(reg_name));
+        let value = self.pop_stack_u16();
+        self.reg16_write(reg_name, value);
+        // The low nibble of F doesn't exist in hardware, so it always reads back as zero.
+        self.f &= 0xF0;
+        self.pc += 1;
+        12
     }
 
-    fn add(&mut self, operand: Operand) {
-        let orig = self.a;
-        let value = match operand {
-            Operand::Reg8(reg_name) => {
-                println!("ADD A,{}", self.reg8_string(reg_name));
-                let value = self.reg8_read(reg_name);
-                self.a += value;
-                self.pc += 1;
-                value
-            },
-            Operand::HLAddr => {
-                println!("ADD A,(HL)");
-                let value = self.read_hladdr_u8();
-                self.a += value;
-                self.pc += 1;
-                value
-            },
-            Operand::Immediate8 => {
-                let value = self.mem.read_u8(self.pc+1);
-                println!("ADD A,{:02X}", value);
-                self.a += value;
-                self.pc += 2;
-                value
-            },
-            _ => unreachable!("ADD only supports Reg8, HLAddr, and Immediate8.")
-        };
+    fn sp_plus_r8(&mut self) -> u16 {
+        let value = self.mem.read_u8(self.pc+1);
+        let sp = self.sp;
+        let halfcarry = (sp & 0xF) + (value as u16 & 0xF) > 0xF;
+        let carry = (sp & 0xFF) + value as u16 > 0xFF;
+        self.flags_write(false, false, halfcarry, carry);
+        sp.wrapping_add(value as i8 as u16)
+    }
 
-        self.f = 0;
-        if self.a == 0 {
-            self.set_f_zero();
-        }
-        let half_carry = ((self.a as i8 & 0xF) + (value as i8 & 0xF)) & 0x10;
-        if half_carry == 0x10 {
-            self.set_f_halfcarry();
-        }
-        if self.a < orig {
-            self.set_f_carry();
-        }
+    fn ld_hl_sp_r8(&mut self) -> u32 {
+        trace!("LD HL,SP+{:02X}", self.mem.read_u8(self.pc+1) as i8);
+        let value = self.sp_plus_r8();
+        self.reg16_write(Reg16Name::HL, value);
+        self.pc += 2;
+        12
+    }
+
+    fn add_sp_r8(&mut self) -> u32 {
+        trace!("ADD SP,{:02X}", self.mem.read_u8(self.pc+1) as i8);
+        self.sp = self.sp_plus_r8();
+        self.pc += 2;
+        16
     }
 
-    fn sub(&mut self, operand: Operand) {
+    fn add_hl(&mut self, operand: Operand) -> u32 {
         let value = match operand {
-            Operand::Reg8(reg_name) => {
-                println!("SUB A,{}", self.reg8_string(reg_name));
-                let value = self.reg8_read(reg_name);
-                self.a -= value;
-                self.pc += 1;
-                value
-            },
-            Operand::HLAddr => {
-                println!("SUB A,(HL)");
-                let value = self.read_hladdr_u8();
-                self.a -= value;
-                self.pc += 1;
-                value
+            Operand::Reg16(reg_name) => {
+                trace!("ADD HL,{}", self.reg16_string(reg_name));
+                self.reg16_read(reg_name)
             },
-            Operand::Immediate8 => {
-                let value = self.mem.read_u8(self.pc+1);
-                println!("SUB A,{:02X}", value);
-                self.a -= value;
-                self.pc += 2;
-                value
+            Operand::StackPointer => {
+                trace!("ADD HL,SP");
+                self.sp
             },
-            _ => unreachable!("SUB only supports Reg8, HLAddr, and Immediate8.")
+            _ => unreachable!("ADD HL only supports Reg16 and StackPointer.")
         };
+        let orig = self.reg16_read(Reg16Name::HL);
+        let (result, carry) = orig.overflowing_add(value);
+        self.reg16_write(Reg16Name::HL, result);
+        let zero = self.is_f_zero();
+        self.flags_write(zero, false, (orig & 0xFFF) + (value & 0xFFF) > 0xFFF, carry);
+        self.pc += 1;
+        8
+    }
 
-        self.f = 0b01000_000u8;
-        if value == self.a {
-            self.set_f_zero();
-        } else if self.a < value {
-            self.set_f_carry();
-        }
-        let half_carry = ((self.a as i8 & 0xF) - (value as i8 & 0xF)) & 0x10;
-        if half_carry == 0x10 {
-            self.set_f_halfcarry();
-        }
+    fn add(&mut self, operand: Operand) -> u32 {
+        trace!("ADD A,{}", self.operand8_string(operand));
+        let value = self.operand8_read(operand);
+        let orig = self.a;
+        let (result, carry) = orig.overflowing_add(value);
+        self.a = result;
+        self.flags_write(result == 0, false, (orig & 0xF) + (value & 0xF) > 0xF, carry);
+        self.pc += 1;
+        self.operand8_cycles(operand)
     }
 
-    fn and(&mut self, operand: Operand) {
-        match operand {
-            Operand::Reg8(reg_name) => {
-                println!("AND {}", self.reg8_string(reg_name));
-                self.a &= self.reg8_read(reg_name);
-                self.pc += 1;
-            },
-            Operand::HLAddr => {
-                println!("AND (HL)");
-                let value = self.read_hladdr_u8();
-                self.a &= value;
-                self.pc += 1;
-            },
-            Operand::Immediate8 => {
-                let value = self.mem.read_u8(self.pc+1);
-                println!("AND {:02X}", value);
-                self.a &= value;
-                self.pc += 2;
-            },
-            _ => unreachable!("AND only supports Reg8, HLAddr, and Immediate8.")
-        };
-        self.f = 0b0010_0000u8;
-        if self.a == 0 {
-            self.set_f_zero();
-        }
+    fn adc(&mut self, operand: Operand) -> u32 {
+        trace!("ADC A,{}", self.operand8_string(operand));
+        let value = self.operand8_read(operand);
+        let carry_in = self.is_f_carry() as u8;
+        let orig = self.a;
+        let result = orig.wrapping_add(value).wrapping_add(carry_in);
+        self.a = result;
+        self.flags_write(
+            result == 0,
+            false,
+            (orig & 0xF) + (value & 0xF) + carry_in > 0xF,
+            orig as u16 + value as u16 + carry_in as u16 > 0xFF
+        );
+        self.pc += 1;
+        self.operand8_cycles(operand)
     }
 
-    fn xor(&mut self, operand: Operand) {
-        match operand {
-            Operand::Reg8(reg_name) => {
-                println!("XOR {}", self.reg8_string(reg_name));
-                self.a ^= self.reg8_read(reg_name);
-                self.pc += 1;
-            },
-            Operand::HLAddr => {
-                println!("XOR (HL)");
-                let value = self.read_hladdr_u8();
-                self.a ^= value;
-                self.pc += 1;
-            },
-            Operand::Immediate8 => {
-                let value = self.mem.read_u8(self.pc+1);
-                println!("XOR {:02X}", value);
-                self.a ^= value;
-                self.pc += 2;
-            },
-            _ => unreachable!("XOR only supports Reg8, HLAddr, and Immediate8.")
-        };
-        self.f = 0;
-        if self.a == 0 {
-            self.set_f_zero();
-        }
+    fn sub(&mut self, operand: Operand) -> u32 {
+        trace!("SUB A,{}", self.operand8_string(operand));
+        let value = self.operand8_read(operand);
+        let orig = self.a;
+        let result = orig.wrapping_sub(value);
+        self.a = result;
+        self.flags_write(result == 0, true, (orig & 0xF) < (value & 0xF), orig < value);
+        self.pc += 1;
+        self.operand8_cycles(operand)
+    }
+
+    fn sbc(&mut self, operand: Operand) -> u32 {
+        trace!("SBC A,{}", self.operand8_string(operand));
+        let value = self.operand8_read(operand);
+        let carry_in = self.is_f_carry() as u8;
+        let orig = self.a;
+        let result = orig.wrapping_sub(value).wrapping_sub(carry_in);
+        self.a = result;
+        self.flags_write(
+            result == 0,
+            true,
+            (orig & 0xF) < (value & 0xF) + carry_in,
+            (orig as u16) < value as u16 + carry_in as u16
+        );
+        self.pc += 1;
+        self.operand8_cycles(operand)
+    }
+
+    fn and(&mut self, operand: Operand) -> u32 {
+        trace!("AND {}", self.operand8_string(operand));
+        self.a &= self.operand8_read(operand);
+        let zero = self.a == 0;
+        self.flags_write(zero, false, true, false);
+        self.pc += 1;
+        self.operand8_cycles(operand)
+    }
+
+    fn xor(&mut self, operand: Operand) -> u32 {
+        trace!("XOR {}", self.operand8_string(operand));
+        self.a ^= self.operand8_read(operand);
+        let zero = self.a == 0;
+        self.flags_write(zero, false, false, false);
+        self.pc += 1;
+        self.operand8_cycles(operand)
+    }
+
+    fn or(&mut self, operand: Operand) -> u32 {
+        trace!("OR {}", self.operand8_string(operand));
+        self.a |= self.operand8_read(operand);
+        let zero = self.a == 0;
+        self.flags_write(zero, false, false, false);
+        self.pc += 1;
+        self.operand8_cycles(operand)
+    }
+
+    fn cp(&mut self, operand: Operand) -> u32 {
+        trace!("CP {}", self.operand8_string(operand));
+        let value = self.operand8_read(operand);
+        self.pc += 1;
+
+        let orig = self.a;
+        self.flags_write(orig == value, true, (orig & 0xF) < (value & 0xF), orig < value);
+        self.operand8_cycles(operand)
+    }
+
+    fn cb_shift(&mut self, operand: Operand, mnemonic: &str, op: fn(u8, bool) -> (u8, bool)) -> u32 {
+        trace!("{} {}", mnemonic, self.operand8_string(operand));
+        let value = self.operand8_read(operand);
+        let (result, carry) = op(value, self.is_f_carry());
+        self.operand8_write(operand, result);
+        self.flags_write(result == 0, false, false, carry);
+        self.pc += 2;
+        self.cb_cycles(operand)
     }
 
-    fn or(&mut self, operand: Operand) {
+    fn rlc(&mut self, operand: Operand) -> u32 {
+        self.cb_shift(operand, "RLC", |value, _| (value.rotate_left(1), value & 0x80 != 0))
+    }
+
+    fn rrc(&mut self, operand: Operand) -> u32 {
+        self.cb_shift(operand, "RRC", |value, _| (value.rotate_right(1), value & 0x01 != 0))
+    }
+
+    fn rl(&mut self, operand: Operand) -> u32 {
+        self.cb_shift(operand, "RL", |value, carry| ((value << 1) | carry as u8, value & 0x80 != 0))
+    }
+
+    fn rr(&mut self, operand: Operand) -> u32 {
+        self.cb_shift(operand, "RR", |value, carry| ((value >> 1) | ((carry as u8) << 7), value & 0x01 != 0))
+    }
+
+    fn sla(&mut self, operand: Operand) -> u32 {
+        self.cb_shift(operand, "SLA", |value, _| (value << 1, value & 0x80 != 0))
+    }
+
+    fn sra(&mut self, operand: Operand) -> u32 {
+        // Arithmetic shift, so bit 7 is preserved.
+        self.cb_shift(operand, "SRA", |value, _| ((value >> 1) | (value & 0x80), value & 0x01 != 0))
+    }
+
+    fn swap(&mut self, operand: Operand) -> u32 {
+        self.cb_shift(operand, "SWAP", |value, _| (value.rotate_left(4), false))
+    }
+
+    fn srl(&mut self, operand: Operand) -> u32 {
+        self.cb_shift(operand, "SRL", |value, _| (value >> 1, value & 0x01 != 0))
+    }
+
+    fn bit(&mut self, bit: u8, operand: Operand) -> u32 {
+        trace!("BIT {},{}", bit, self.operand8_string(operand));
+        let value = self.operand8_read(operand);
+        let carry = self.is_f_carry();
+        self.flags_write(value & (1 << bit) == 0, false, true, carry);
+        self.pc += 2;
+        // BIT only reads (HL), so it skips the write-back cycle.
         match operand {
-            Operand::Reg8(reg_name) => {
-                println!("OR {}", self.reg8_string(reg_name));
-                self.a |= self.reg8_read(reg_name);
-                self.pc += 1;
-            },
-            Operand::HLAddr => {
-                println!("OR (HL)");
-                let value = self.read_hladdr_u8();
-                self.a |= value;
-                self.pc += 1;
-            },
-            Operand::Immediate8 => {
-                let value = self.mem.read_u8(self.pc+1);
-                println!("OR {:02X}", value);
-                self.a |= value;
-                self.pc += 2;
-            },
-            _ => unreachable!("OR only supports Reg8, HLAddr, and Immediate8.")
-        };
-        self.f = 0;
-        if self.a == 0 {
-            self.set_f_zero();
+            Operand::HLAddr => 12,
+            _ => 8
         }
     }
 
-    fn cp(&mut self, operand: Operand) {
-        let value = match operand {
-            Operand::Reg8(reg_name) => {
-                println!("CP {}", self.reg8_string(reg_name));
-                let value = self.reg8_read(reg_name);
-                self.pc += 1;
-                value
-            },
-            Operand::HLAddr => {
-                println!("CP (HL)");
-                let value = self.read_hladdr_u8();
-                self.pc += 1;
-                value
-            },
-            Operand::Immediate8 => {
-                let value = self.mem.read_u8(self.pc+1);
-                println!("CP {:02X}", value);
-                self.pc += 2;
-                value
-            },
-            _ => unreachable!("CP only supports Reg8, HLAddr, and Immediate8.")
-        };
+    fn res(&mut self, bit: u8, operand: Operand) -> u32 {
+        trace!("RES {},{}", bit, self.operand8_string(operand));
+        let value = self.operand8_read(operand) & !(1 << bit);
+        self.operand8_write(operand, value);
+        self.pc += 2;
+        self.cb_cycles(operand)
+    }
 
-        // Remove later!
-        // 0x91 = 145 = first line of vblank period.
-        let hack = self.pc == 111 && value == 0x91;
+    fn set(&mut self, bit: u8, operand: Operand) -> u32 {
+        trace!("SET {},{}", bit, self.operand8_string(operand));
+        let value = self.operand8_read(operand) | (1 << bit);
+        self.operand8_write(operand, value);
+        self.pc += 2;
+        self.cb_cycles(operand)
+    }
+}
 
-        self.f = 0b01000_000u8;
-        if value == self.a || hack {
-            self.set_f_zero();
-        } else if self.a < value {
-            self.set_f_carry();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use joypad::*;
+
+    const Z: u8 = 0b1000_0000u8;
+    const N: u8 = 0b0100_0000u8;
+    const H: u8 = 0b0010_0000u8;
+    const C: u8 = 0b0001_0000u8;
+
+    // A CPU about to run the given program from work RAM.
+    fn cpu_with_program(program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new();
+        for (i, &byte) in program.iter().enumerate() {
+            cpu.mem.write_u8(0xC000 + i as u16, byte);
         }
-        let half_carry = ((self.a as i8 & 0xF) - (value as i8 & 0xF)) & 0x10;
-        if half_carry == 0x10 {
-            self.set_f_halfcarry();
+        cpu.pc = 0xC000;
+        cpu
+    }
+
+    fn run(cpu: &mut Cpu, instructions: usize) {
+        for _ in 0 .. instructions {
+            cpu.step();
+        }
+    }
+
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        // EI; NOP
+        let mut cpu = cpu_with_program(&[0xFB, 0x00]);
+        cpu.mem.write_u8(0xFFFF, 0x01);
+        cpu.mem.write_u8(0xFF0F, 0x01);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0xC002);
+        assert_eq!(cpu.step(), 20);
+        assert_eq!(cpu.pc, 0x0040);
+        assert_eq!(cpu.mem.read_u16(cpu.sp), 0xC002);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.mem.read_u8(0xFF0F), 0xE0);
+    }
+
+    #[test]
+    fn interrupts_dispatch_by_priority() {
+        let vectors = [0x40, 0x48, 0x50, 0x58, 0x60];
+        let mut cpu = cpu_with_program(&[]);
+        cpu.mem.write_u8(0xFFFF, 0x1F);
+        // Everything but VBlank, which goes to the lowest bit next.
+        cpu.mem.write_u8(0xFF0F, 0x1E);
+        for (bit, &vector) in vectors.iter().enumerate().skip(1) {
+            cpu.ime = true;
+            assert_eq!(cpu.step(), 20);
+            assert_eq!(cpu.pc, vector);
+            // Only the dispatched interrupt's flag gets cleared.
+            assert_eq!(cpu.mem.read_u8(0xFF0F), 0xE0 | (0x1F << (bit + 1) & 0x1F));
         }
+        // Interrupts that aren't enabled stay pending, but never dispatch.
+        cpu.mem.write_u8(0xFFFF, 0x00);
+        cpu.mem.write_u8(0xFF0F, 0x01);
+        cpu.pc = 0xC000;
+        cpu.ime = true;
+        cpu.step();
+        assert!(cpu.pc != 0x0040);
+        assert_eq!(cpu.mem.read_u8(0xFF0F), 0xE1);
+    }
+
+    #[test]
+    fn halt_wakes_without_ime() {
+        // HALT; INC A
+        let mut cpu = cpu_with_program(&[0x76, 0x3C]);
+        cpu.a = 0x00;
+        cpu.mem.write_u8(0xFFFF, 0x04);
+        run(&mut cpu, 3);
+        assert!(cpu.halted);
+        assert_eq!(cpu.pc, 0xC001);
+        // Waking takes a machine cycle, and with IME off nothing is dispatched.
+        cpu.mem.write_u8(0xFF0F, 0x04);
+        assert_eq!(cpu.step(), 4);
+        assert!(!cpu.halted);
+        run(&mut cpu, 1);
+        assert_eq!((cpu.a, cpu.pc), (0x01, 0xC002));
+        assert_eq!(cpu.mem.read_u8(0xFF0F), 0xE4);
+    }
+
+    #[test]
+    fn halt_bug_runs_the_next_byte_twice() {
+        // HALT; INC A, with an interrupt already pending and IME off.
+        let mut cpu = cpu_with_program(&[0x76, 0x3C, 0x00]);
+        cpu.a = 0x00;
+        cpu.mem.write_u8(0xFFFF, 0x04);
+        cpu.mem.write_u8(0xFF0F, 0x04);
+        run(&mut cpu, 1);
+        assert!(!cpu.halted);
+        run(&mut cpu, 2);
+        assert_eq!((cpu.a, cpu.pc), (0x02, 0xC002));
+    }
+
+    #[test]
+    fn stop_waits_for_a_joypad_press() {
+        // STOP; INC A
+        let mut cpu = cpu_with_program(&[0x10, 0x00, 0x3C]);
+        cpu.a = 0x00;
+        cpu.mem.write_u8(0xFF00, 0x10);
+        run(&mut cpu, 10);
+        assert!(cpu.stopped);
+        assert_eq!((cpu.a, cpu.pc), (0x00, 0xC002));
+        cpu.mem.set_button(Button::A, true);
+        run(&mut cpu, 2);
+        assert!(!cpu.stopped);
+        assert_eq!((cpu.a, cpu.pc), (0x01, 0xC003));
+    }
+
+    #[test]
+    fn branches_take_longer_when_taken() {
+        // Each opcode with its flags when taken and not taken, and the cycles for each.
+        let branches = [
+            (0x20, 0, Z, 12, 8),      // JR NZ
+            (0x28, Z, 0, 12, 8),      // JR Z
+            (0x30, 0, C, 12, 8),      // JR NC
+            (0x38, C, 0, 12, 8),      // JR C
+            (0xC2, 0, Z, 16, 12),     // JP NZ
+            (0xCA, Z, 0, 16, 12),     // JP Z
+            (0xD2, 0, C, 16, 12),     // JP NC
+            (0xDA, C, 0, 16, 12),     // JP C
+            (0xC4, 0, Z, 24, 12),     // CALL NZ
+            (0xCC, Z, 0, 24, 12),     // CALL Z
+            (0xD4, 0, C, 24, 12),     // CALL NC
+            (0xDC, C, 0, 24, 12),     // CALL C
+            (0xC0, 0, Z, 20, 8),      // RET NZ
+            (0xC8, Z, 0, 20, 8),      // RET Z
+            (0xD0, 0, C, 20, 8),      // RET NC
+            (0xD8, C, 0, 20, 8)       // RET C
+        ];
+        for &(opcode, taken_flags, not_taken_flags, taken_cycles, not_taken_cycles) in branches.iter() {
+            for &(flags, cycles) in [(taken_flags, taken_cycles), (not_taken_flags, not_taken_cycles)].iter() {
+                let mut cpu = cpu_with_program(&[opcode, 0x00, 0xC1]);
+                cpu.f = flags;
+                assert_eq!(cpu.step(), cycles, "opcode {:02X} with flags {:02X}", opcode, flags);
+            }
+        }
+
+        // And their unconditional versions: JR, JP, CALL, RET, RETI and JP (HL).
+        for &(opcode, cycles) in [(0x18, 12), (0xC3, 16), (0xCD, 24), (0xC9, 16), (0xD9, 16), (0xE9, 4)].iter() {
+            let mut cpu = cpu_with_program(&[opcode, 0x00, 0xC1]);
+            assert_eq!(cpu.step(), cycles, "opcode {:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn daa_adjusts_after_addition() {
+        // ADD A,38; DAA
+        let mut cpu = cpu_with_program(&[0xC6, 0x38, 0x27]);
+        cpu.a = 0x45;
+        run(&mut cpu, 2);
+        assert_eq!((cpu.a, cpu.f), (0x83, 0));
+
+        // ADD A,01; DAA, which carries out of the top digit.
+        let mut cpu = cpu_with_program(&[0xC6, 0x01, 0x27]);
+        cpu.a = 0x99;
+        run(&mut cpu, 2);
+        assert_eq!((cpu.a, cpu.f), (0x00, Z | C));
+    }
+
+    #[test]
+    fn daa_adjusts_after_subtraction() {
+        // SUB 01; DAA
+        let mut cpu = cpu_with_program(&[0xD6, 0x01, 0x27]);
+        cpu.a = 0x10;
+        run(&mut cpu, 2);
+        assert_eq!((cpu.a, cpu.f), (0x09, N));
+
+        // SUB 01; DAA, which borrows out of the top digit.
+        let mut cpu = cpu_with_program(&[0xD6, 0x01, 0x27]);
+        cpu.a = 0x00;
+        run(&mut cpu, 2);
+        assert_eq!((cpu.a, cpu.f), (0x99, N | C));
+    }
+
+    #[test]
+    fn adc_includes_carry_in_both_carries() {
+        // ADC A,00 with the carry set.
+        let mut cpu = cpu_with_program(&[0xCE, 0x00]);
+        cpu.a = 0x0F;
+        cpu.f = C;
+        run(&mut cpu, 1);
+        assert_eq!((cpu.a, cpu.f), (0x10, H));
+
+        let mut cpu = cpu_with_program(&[0xCE, 0x00]);
+        cpu.a = 0xFF;
+        cpu.f = C;
+        run(&mut cpu, 1);
+        assert_eq!((cpu.a, cpu.f), (0x00, Z | H | C));
+    }
+
+    #[test]
+    fn sbc_includes_carry_in_both_borrows() {
+        // SBC A,0F with the carry set.
+        let mut cpu = cpu_with_program(&[0xDE, 0x0F]);
+        cpu.a = 0x10;
+        cpu.f = C;
+        run(&mut cpu, 1);
+        assert_eq!((cpu.a, cpu.f), (0x00, Z | N | H));
+
+        let mut cpu = cpu_with_program(&[0xDE, 0x00]);
+        cpu.a = 0x00;
+        cpu.f = C;
+        run(&mut cpu, 1);
+        assert_eq!((cpu.a, cpu.f), (0xFF, N | H | C));
+    }
+
+    #[test]
+    fn add_sp_r8_sets_carries_from_the_low_byte() {
+        // ADD SP,01
+        let mut cpu = cpu_with_program(&[0xE8, 0x01]);
+        cpu.sp = 0x00FF;
+        cpu.f = Z | N;
+        run(&mut cpu, 1);
+        assert_eq!((cpu.sp, cpu.f), (0x0100, H | C));
+
+        // ADD SP,-1, which borrows from the high byte without setting any flag.
+        let mut cpu = cpu_with_program(&[0xE8, 0xFF]);
+        cpu.sp = 0x1000;
+        run(&mut cpu, 1);
+        assert_eq!((cpu.sp, cpu.f), (0x0FFF, 0));
+    }
+
+    #[test]
+    fn ld_hl_sp_r8_never_sets_zero() {
+        // LD HL,SP+08
+        let mut cpu = cpu_with_program(&[0xF8, 0x08]);
+        cpu.sp = 0xFFF8;
+        run(&mut cpu, 1);
+        assert_eq!((cpu.h, cpu.l, cpu.sp), (0x00, 0x00, 0xFFF8));
+        assert_eq!(cpu.f, H | C);
+    }
+
+    #[test]
+    fn pop_af_clears_the_low_nibble_of_f() {
+        // POP AF
+        let mut cpu = cpu_with_program(&[0xF1]);
+        cpu.sp = 0xD000;
+        cpu.mem.write_u16(0xD000, 0x12FF);
+        run(&mut cpu, 1);
+        assert_eq!((cpu.a, cpu.f, cpu.sp), (0x12, 0xF0, 0xD002));
     }
 }
@@ -1,19 +1,55 @@
-use std::io::Read;
+use std::io::{self, Read, Write};
 use std::fs::File;
-use std::str;
+use std::path::{Path, PathBuf};
+use std::fmt;
 use util::*;
 use cpu::*;
+use ppu::*;
+use joypad::*;
+use mbc::*;
+use cartridge::*;
+use header::*;
 
 pub enum CartridgeValidationError {
     InvalidNintendoLogo,
-    InvalidGameTitle(str::Utf8Error),
-    /*InvalidSGBIndicator,
-    InvalidCartridgeType,
-    InvalidROMSize,
-    InvalidRAMSize,
-    InvalidLicenseeCode,
-    FailedComplementCheck,
-    FailedChecksumCheck*/
+    // A real cartridge type, just not one that's emulated.
+    UnsupportedCartridgeType(u8),
+    InvalidSGBIndicator(u8),
+    InvalidCartridgeType(u8),
+    InvalidROMSize(u8),
+    InvalidRAMSize(u8),
+    InvalidLicenseeCode(LicenseeCode),
+    // The header checksum at 0x14D, which the boot ROM refuses to continue without.
+    FailedComplementCheck { expected: u8, found: u8 },
+    // The global checksum at 0x14E, which nothing checks.
+    FailedChecksumCheck { expected: u16, found: u16 }
+}
+
+impl fmt::Display for CartridgeValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CartridgeValidationError::InvalidNintendoLogo =>
+                write!(f, "Nintendo logo (0x104 .. 0x133) did not match expected value."),
+            CartridgeValidationError::UnsupportedCartridgeType(t) =>
+                write!(f, "Cartridge type {:02X} ({}) isn't supported.", t, CartridgeType::from_code(t).name()),
+            CartridgeValidationError::InvalidSGBIndicator(v) =>
+                write!(f, "SGB indicator (0x146) should be 00 or 03, but is {:02X}.", v),
+            CartridgeValidationError::InvalidCartridgeType(t) =>
+                write!(f, "Cartridge type (0x147) {:02X} isn't a known type.", t),
+            CartridgeValidationError::InvalidROMSize(code) =>
+                write!(f, "ROM size (0x148) {:02X} isn't a known size.", code),
+            CartridgeValidationError::InvalidRAMSize(code) =>
+                write!(f, "RAM size (0x149) {:02X} isn't a known size.", code),
+            CartridgeValidationError::InvalidLicenseeCode(LicenseeCode::Old(code)) =>
+                write!(f, "Licensee code (0x14B) {:02X} isn't a known licensee.", code),
+            CartridgeValidationError::InvalidLicenseeCode(LicenseeCode::New(code)) =>
+                write!(f, "Licensee code (0x144 .. 0x145) {} isn't a known licensee.", String::from_utf8_lossy(&code)),
+            CartridgeValidationError::FailedComplementCheck { expected, found } =>
+                write!(f, "Header checksum (0x14D) is {:02X}, but should be {:02X}.", found, expected),
+            CartridgeValidationError::FailedChecksumCheck { expected, found } =>
+                write!(f, "Global checksum (0x14E .. 0x14F) is {:04X}, but should be {:04X}.", found, expected)
+        }
+    }
 }
 
 pub type CartridgeValidationResult<T> = Result<T, CartridgeValidationError>;
@@ -30,19 +66,117 @@ fn validate_nintendo_logo(logo: &[u8]) -> CartridgeValidationResult<()> {
         Err(CartridgeValidationError::InvalidNintendoLogo)
     }
 }
-/*
+
+fn validate_sgb_indicator(value: u8) -> CartridgeValidationResult<()> {
+    match value {
+        0x00 | 0x03 => Ok(()),
+        _ => Err(CartridgeValidationError::InvalidSGBIndicator(value))
+    }
+}
+
+fn validate_rom_size(header: &CartridgeHeader) -> CartridgeValidationResult<()> {
+    header.rom_size().map(|_| ()).ok_or(CartridgeValidationError::InvalidROMSize(header.rom_size_code))
+}
+
+fn validate_ram_size(header: &CartridgeHeader) -> CartridgeValidationResult<()> {
+    header.ram_size().map(|_| ()).ok_or(CartridgeValidationError::InvalidRAMSize(header.ram_size_code))
+}
+
+fn validate_licensee_code(header: &CartridgeHeader) -> CartridgeValidationResult<()> {
+    let code = header.licensee_code;
+    code.name().map(|_| ()).ok_or(CartridgeValidationError::InvalidLicenseeCode(code))
+}
+
+fn validate_header_checksum(header: &CartridgeHeader) -> CartridgeValidationResult<()> {
+    if header.header_checksum == header.expected_header_checksum {
+        Ok(())
+    } else {
+        Err(CartridgeValidationError::FailedComplementCheck {
+            expected: header.expected_header_checksum,
+            found: header.header_checksum
+        })
+    }
+}
+
+fn validate_global_checksum(header: &CartridgeHeader) -> CartridgeValidationResult<()> {
+    if header.global_checksum == header.expected_global_checksum {
+        Ok(())
+    } else {
+        Err(CartridgeValidationError::FailedChecksumCheck {
+            expected: header.expected_global_checksum,
+            found: header.global_checksum
+        })
+    }
+}
+
+// Everything wrong with the header that doesn't stop the ROM from being
+// emulated, in header order.
+fn validate_header(rom: &[u8], header: &CartridgeHeader) -> Vec<CartridgeValidationError> {
+    vec![
+        validate_nintendo_logo(&header.nintendo_logo),
+        validate_sgb_indicator(get_u8(rom, 0x146)),
+        validate_rom_size(header),
+        validate_ram_size(header),
+        validate_licensee_code(header),
+        validate_header_checksum(header),
+        validate_global_checksum(header)
+    ].into_iter().filter_map(|result| result.err()).collect()
+}
+
+// The header runs up to 0x14F, so anything shorter can't be a ROM.
+const HEADER_END: usize = 0x150;
+
 pub enum GameBoyError {
+    Io(io::Error),
+    // How many bytes the ROM actually had.
+    TruncatedRom(usize),
+    // These carry the parsed header, which is still worth reporting on.
+    UnsupportedMapper(CartridgeHeader),
+    InvalidCartridge(CartridgeHeader, CartridgeValidationError)
+}
 
+impl From<io::Error> for GameBoyError {
+    fn from(e: io::Error) -> Self {
+        GameBoyError::Io(e)
+    }
+}
+
+impl GameBoyError {
+    fn from_cartridge_error(header: CartridgeHeader, e: CartridgeValidationError) -> Self {
+        match e {
+            CartridgeValidationError::UnsupportedCartridgeType(_) => GameBoyError::UnsupportedMapper(header),
+            _ => GameBoyError::InvalidCartridge(header, e)
+        }
+    }
+}
+
+impl fmt::Display for GameBoyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GameBoyError::Io(ref e) =>
+                write!(f, "Failed to read ROM: {}", e),
+            GameBoyError::TruncatedRom(len) =>
+                write!(f, "ROM is only {} bytes, too short to hold a header ({} bytes).", len, HEADER_END),
+            GameBoyError::UnsupportedMapper(ref header) => {
+                let t = header.cartridge_type;
+                write!(f, "Cartridge type {:02X} ({}) isn't supported.", t.code(), t.name())
+            },
+            GameBoyError::InvalidCartridge(_, ref e) =>
+                write!(f, "{}", e)
+        }
+    }
 }
 
 pub type GameBoyResult<T> = Result<T, GameBoyError>;
-*/
-pub struct GameBoy {
-    pub rom: Vec<u8>,
 
-    pub game_title: String,
+// 154 lines of 456 cycles each.
+const FRAME_CYCLES: u64 = 70224;
 
-    cartridge_type: u8,
+pub struct GameBoy {
+    header: CartridgeHeader,
+
+    // Where battery-backed cartridge RAM is persisted, if the cartridge has any.
+    save_path: Option<PathBuf>,
 
     cpu: Cpu
 }
@@ -50,11 +184,9 @@ pub struct GameBoy {
 impl Default for GameBoy {
     fn default() -> Self {
         GameBoy {
-            rom: Vec::new(),
-
-            game_title: String::new(),
+            header: CartridgeHeader::default(),
 
-            cartridge_type: 0x0,
+            save_path: None,
 
             cpu: Cpu::new()
         }
@@ -66,25 +198,121 @@ impl GameBoy {
         Self::default()
     }
 
-    pub fn load_rom(&mut self, rom_path: &str) -> CartridgeValidationResult<()> {
-        self.rom = {
-            let mut f = File::open(rom_path).expect("ROM file not found.");
-            let mut v = Vec::new();
-            f.read_to_end(&mut v).expect("ROM reading failed.");
-            v
+    // The pixel FIFO renderer is slower, but needed for mid-scanline effects.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.cpu.mem_mut().set_renderer(renderer);
+    }
+
+    // Fails if the ROM can't be emulated at all; otherwise returns any
+    // problems with the header as warnings. With no path to save next to,
+    // battery-backed RAM isn't persisted.
+    pub fn load_rom_bytes(&mut self, rom: Vec<u8>) -> GameBoyResult<Vec<CartridgeValidationError>> {
+        if rom.len() < HEADER_END {
+            return Err(GameBoyError::TruncatedRom(rom.len()));
+        }
+        // Nothing is replaced until the cartridge is known to work, so a
+        // failed load leaves whatever was loaded before intact.
+        let header = CartridgeHeader::from_rom(&rom);
+        let warnings = validate_header(&rom, &header);
+        // The cartridge owns the ROM from here on.
+        let cartridge = match new_cartridge(rom) {
+            Ok(cartridge) => cartridge,
+            Err(e) => return Err(GameBoyError::from_cartridge_error(header, e))
         };
 
-        self.game_title = try!(str::from_utf8(&self.rom[0x134 .. 0x142+1])
-            .map_err(CartridgeValidationError::InvalidGameTitle)).to_string();
+        self.header = header;
+        self.save_path = None;
+        self.cpu.mem_mut().insert_cartridge(cartridge);
+
+        Ok(warnings)
+    }
 
-        try!(validate_nintendo_logo(&self.rom[0x104 .. 0x133+1]));
+    pub fn load_rom_from<R: Read>(&mut self, mut reader: R) -> GameBoyResult<Vec<CartridgeValidationError>> {
+        let mut rom = Vec::new();
+        try!(reader.read_to_end(&mut rom));
+        self.load_rom_bytes(rom)
+    }
 
-        self.cartridge_type = get_u8(&self.rom, 0x147);
+    // Like load_rom_bytes, but battery-backed RAM is saved alongside the ROM.
+    pub fn load_rom(&mut self, rom_path: &str) -> GameBoyResult<Vec<CartridgeValidationError>> {
+        let f = try!(File::open(rom_path));
+        let warnings = try!(self.load_rom_from(f));
 
-        Ok(())
+        if has_battery(self.header.cartridge_type.code()) {
+            self.save_path = Some(Path::new(rom_path).with_extension("sav"));
+        }
+        self.load_save();
+
+        Ok(warnings)
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    fn load_save(&mut self) {
+        if let Some(ref save_path) = self.save_path {
+            if let Ok(mut f) = File::open(save_path) {
+                let mut v = Vec::new();
+                match f.read_to_end(&mut v) {
+                    Ok(_) => self.cpu.mem_mut().load_save_ram(&v),
+                    Err(e) => println!("[Warning] Failed to read save file: {}", e)
+                }
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        if let Some(ref save_path) = self.save_path {
+            let result = File::create(save_path)
+                .and_then(|mut f| f.write_all(&self.cpu.mem().save_ram()));
+            if let Err(e) = result {
+                println!("[Warning] Failed to write save file: {}", e);
+            }
+        }
+    }
+
+    // Runs until the PPU finishes a frame, or for as long as a frame would
+    // take if the LCD is off.
+    pub fn run_frame(&mut self) {
+        let start = self.cpu.cycles();
+        loop {
+            self.cpu.step();
+            if self.cpu.mem_mut().take_save_request() {
+                self.save();
+            }
+            if self.cpu.mem_mut().take_frame_ready() || self.cpu.cycles() - start >= FRAME_CYCLES {
+                break;
+            }
+        }
+    }
+
+    // Called with the new motor state whenever a rumble cartridge turns it on or off.
+    pub fn set_rumble_callback<F: FnMut(bool) + 'static>(&mut self, callback: F) {
+        self.cpu.mem_mut().set_rumble_callback(Box::new(callback));
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.cpu.mem_mut().set_button(button, pressed);
+    }
+
+    // Audio comes out at 44100 Hz unless told otherwise.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.cpu.mem_mut().set_sample_rate(sample_rate);
+    }
+
+    // Copies out as many interleaved left/right samples as are ready and fit,
+    // returning how many were written.
+    pub fn pull_samples(&mut self, buffer: &mut [f32]) -> usize {
+        self.cpu.mem_mut().pull_samples(buffer)
+    }
+
+    // 160x144 shades from 0 (lightest) to 3 (darkest), row by row.
+    pub fn framebuffer(&self) -> &[u8] {
+        self.cpu.mem().framebuffer()
     }
 
-    pub fn run(&mut self) {
-        self.cpu.run(&self.rom);
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles()
     }
 }
@@ -0,0 +1,583 @@
+use std::fmt;
+use util::*;
+use mbc::*;
+
+// Parsing, lookup tables and checksums for the cartridge header at 0x100-0x14F.
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CartridgeType {
+    RomOnly,
+    Mbc1,
+    Mbc1Ram,
+    Mbc1RamBattery,
+    Mbc2,
+    Mbc2Battery,
+    RomRam,
+    RomRamBattery,
+    Mmm01,
+    Mmm01Ram,
+    Mmm01RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
+    Mbc5,
+    Mbc5Ram,
+    Mbc5RamBattery,
+    Mbc5Rumble,
+    Mbc5RumbleRam,
+    Mbc5RumbleRamBattery,
+    Mbc6,
+    Mbc7SensorRumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery,
+    Unknown(u8)
+}
+
+// (code at 0x147, type, name as printed on cartridge labels and in rgbfix)
+const CARTRIDGE_TYPES: [(u8, CartridgeType, &'static str); 28] = [
+    (0x00, CartridgeType::RomOnly, "ROM ONLY"),
+    (0x01, CartridgeType::Mbc1, "MBC1"),
+    (0x02, CartridgeType::Mbc1Ram, "MBC1+RAM"),
+    (0x03, CartridgeType::Mbc1RamBattery, "MBC1+RAM+BATTERY"),
+    (0x05, CartridgeType::Mbc2, "MBC2"),
+    (0x06, CartridgeType::Mbc2Battery, "MBC2+BATTERY"),
+    (0x08, CartridgeType::RomRam, "ROM+RAM"),
+    (0x09, CartridgeType::RomRamBattery, "ROM+RAM+BATTERY"),
+    (0x0B, CartridgeType::Mmm01, "MMM01"),
+    (0x0C, CartridgeType::Mmm01Ram, "MMM01+RAM"),
+    (0x0D, CartridgeType::Mmm01RamBattery, "MMM01+RAM+BATTERY"),
+    (0x0F, CartridgeType::Mbc3TimerBattery, "MBC3+TIMER+BATTERY"),
+    (0x10, CartridgeType::Mbc3TimerRamBattery, "MBC3+TIMER+RAM+BATTERY"),
+    (0x11, CartridgeType::Mbc3, "MBC3"),
+    (0x12, CartridgeType::Mbc3Ram, "MBC3+RAM"),
+    (0x13, CartridgeType::Mbc3RamBattery, "MBC3+RAM+BATTERY"),
+    (0x19, CartridgeType::Mbc5, "MBC5"),
+    (0x1A, CartridgeType::Mbc5Ram, "MBC5+RAM"),
+    (0x1B, CartridgeType::Mbc5RamBattery, "MBC5+RAM+BATTERY"),
+    (0x1C, CartridgeType::Mbc5Rumble, "MBC5+RUMBLE"),
+    (0x1D, CartridgeType::Mbc5RumbleRam, "MBC5+RUMBLE+RAM"),
+    (0x1E, CartridgeType::Mbc5RumbleRamBattery, "MBC5+RUMBLE+RAM+BATTERY"),
+    (0x20, CartridgeType::Mbc6, "MBC6"),
+    (0x22, CartridgeType::Mbc7SensorRumbleRamBattery, "MBC7+SENSOR+RUMBLE+RAM+BATTERY"),
+    (0xFC, CartridgeType::PocketCamera, "POCKET CAMERA"),
+    (0xFD, CartridgeType::BandaiTama5, "BANDAI TAMA5"),
+    (0xFE, CartridgeType::HuC3, "HuC3"),
+    (0xFF, CartridgeType::HuC1RamBattery, "HuC1+RAM+BATTERY")
+];
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Self {
+        CARTRIDGE_TYPES.iter()
+            .find(|&&(known_code, _, _)| known_code == code)
+            .map_or(CartridgeType::Unknown(code), |&(_, cartridge_type, _)| cartridge_type)
+    }
+
+    pub fn code(&self) -> u8 {
+        match *self {
+            CartridgeType::Unknown(code) => code,
+            _ => CARTRIDGE_TYPES.iter()
+                .find(|&&(_, cartridge_type, _)| cartridge_type == *self)
+                .map(|&(code, _, _)| code)
+                .unwrap()
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        CARTRIDGE_TYPES.iter()
+            .find(|&&(_, cartridge_type, _)| cartridge_type == *self)
+            .map_or("UNKNOWN", |&(_, _, name)| name)
+    }
+
+    pub fn is_known(&self) -> bool {
+        match *self {
+            CartridgeType::Unknown(_) => false,
+            _ => true
+        }
+    }
+}
+
+// ROM size in bytes for the code at 0x148.
+pub fn rom_size(code: u8) -> Option<usize> {
+    match code {
+        0x00 ... 0x08 => Some((ROM_BANK_SIZE * 2) << code),
+        0x52 => Some(ROM_BANK_SIZE * 72),
+        0x53 => Some(ROM_BANK_SIZE * 80),
+        0x54 => Some(ROM_BANK_SIZE * 96),
+        _ => None
+    }
+}
+
+// RAM size in bytes for the code at 0x149.
+pub fn header_ram_size(code: u8) -> Option<usize> {
+    match code {
+        0x00 ... 0x05 => Some(ram_size(code)),
+        _ => None
+    }
+}
+
+// 0x14B, or 0x33 to say the 2 ASCII characters at 0x144 should be used instead.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LicenseeCode {
+    Old(u8),
+    New([u8; 2])
+}
+
+impl LicenseeCode {
+    pub fn from_rom(rom: &[u8]) -> Self {
+        match rom[0x14B] {
+            0x33 => LicenseeCode::New([rom[0x144], rom[0x145]]),
+            code => LicenseeCode::Old(code)
+        }
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            LicenseeCode::Old(code) => old_licensee_name(code),
+            LicenseeCode::New(code) => new_licensee_name(code)
+        }
+    }
+}
+
+fn old_licensee_name(code: u8) -> Option<&'static str> {
+    let name = match code {
+        0x00 => "None",
+        0x01 | 0x31 => "Nintendo",
+        0x08 | 0x38 => "Capcom",
+        0x09 => "HOT-B",
+        0x0A | 0xE0 => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C | 0x6E => "Elite Systems",
+        0x13 | 0x69 => "EA (Electronic Arts)",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F | 0x4A | 0x61 => "Virgin Games Ltd.",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 | 0x7F | 0x97 | 0xC2 => "Kemco",
+        0x29 => "SETA Corporation",
+        0x30 | 0x70 => "Infogrames",
+        0x32 | 0xA2 | 0xB2 => "Bandai",
+        0x34 | 0xA4 => "Konami",
+        0x35 => "HectorSoft",
+        0x39 | 0x9D | 0xD9 => "Banpresto",
+        0x3C => "Entertainment Interactive",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 | 0xEB => "Atlus",
+        0x44 | 0x4D => "Malibu Interactive",
+        0x46 | 0xCF => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 | 0xB0 => "Acclaim Entertainment",
+        0x52 => "Activision",
+        0x53 => "Sammy USA Corporation",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 | 0xDB | 0xFF => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley Company",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C | 0xD6 => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus Interactive",
+        0x67 => "Ocean Software",
+        0x6F => "Electro Brain",
+        0x71 => "Interplay Entertainment",
+        0x72 | 0xAA => "Broderbund",
+        0x73 => "Sculptured Software",
+        0x75 => "The Sales Curve Limited",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "MicroProse",
+        0x80 => "Misawa Entertainment",
+        0x83 => "LOZC G.",
+        0x86 | 0xC4 => "Tokuma Shoten",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai Corp.",
+        0x8E => "Ape Inc.",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft Co.",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 | 0xE3 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB1 => "ASCII Corporation or Nexsoft",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 | 0xCE => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy Corporation",
+        0xC0 | 0xD0 => "Taito",
+        0xC3 => "Square",
+        0xC5 => "Data East",
+        0xC6 => "Tonkin House",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra Games",
+        0xCB => "VAP, Inc.",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xD1 => "SOFEL",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha Co.",
+        0xD7 => "Copya System",
+        0xDA => "Tomy",
+        0xDD => "Nippon Computer Systems",
+        0xDE => "Human Ent.",
+        0xDF => "Altron",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik Ace Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        _ => return None
+    };
+    Some(name)
+}
+
+fn new_licensee_name(code: [u8; 2]) -> Option<&'static str> {
+    let name = match &code {
+        b"00" => "None",
+        b"01" => "Nintendo Research & Development 1",
+        b"08" => "Capcom",
+        b"13" | b"69" => "EA (Electronic Arts)",
+        b"18" | b"38" => "Hudson Soft",
+        b"19" => "B-AI",
+        b"20" => "KSS",
+        b"22" => "Planning Office WADA",
+        b"24" => "PCM Complete",
+        b"25" => "San-X",
+        b"28" => "Kemco",
+        b"29" => "SETA Corporation",
+        b"30" => "Viacom",
+        b"31" => "Nintendo",
+        b"32" => "Bandai",
+        b"33" | b"93" => "Ocean Software/Acclaim Entertainment",
+        b"34" | b"54" => "Konami",
+        b"35" => "HectorSoft",
+        b"37" => "Taito",
+        b"39" => "Banpresto",
+        b"41" => "Ubi Soft",
+        b"42" => "Atlus",
+        b"44" => "Malibu Interactive",
+        b"46" => "Angel",
+        b"47" => "Bullet-Proof Software",
+        b"49" => "Irem",
+        b"50" => "Absolute",
+        b"51" => "Acclaim Entertainment",
+        b"52" => "Activision",
+        b"53" => "Sammy USA Corporation",
+        b"55" => "Hi Tech Expressions",
+        b"56" => "LJN",
+        b"57" => "Matchbox",
+        b"58" => "Mattel",
+        b"59" => "Milton Bradley Company",
+        b"60" => "Titus Interactive",
+        b"61" => "Virgin Games Ltd.",
+        b"64" => "Lucasfilm Games",
+        b"67" => "Ocean Software",
+        b"70" => "Infogrames",
+        b"71" => "Interplay Entertainment",
+        b"72" => "Broderbund",
+        b"73" => "Sculptured Software",
+        b"75" => "The Sales Curve Limited",
+        b"78" => "THQ",
+        b"79" => "Accolade",
+        b"80" => "Misawa Entertainment",
+        b"83" => "LOZC G.",
+        b"86" => "Tokuma Shoten",
+        b"87" => "Tsukuda Original",
+        b"91" => "Chunsoft Co.",
+        b"92" => "Video System",
+        b"95" => "Varie",
+        b"96" => "Yonezawa/S'Pal",
+        b"97" => "Kaneko",
+        b"99" => "Pack-In-Video",
+        b"9H" => "Bottom Up",
+        b"A4" => "Konami (Yu-Gi-Oh!)",
+        b"BL" => "MTO",
+        b"DK" => "Kodansha",
+        _ => return None
+    };
+    Some(name)
+}
+
+// 0x143, which also marks where the title ends on CGB-era cartridges.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CgbFlag {
+    DmgOnly,
+    Supported,
+    Required
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Destination {
+    Japan,
+    Overseas,
+    Unknown(u8)
+}
+
+pub struct CartridgeHeader {
+    // The 48 bytes at 0x104, which the boot ROM compares against its own copy.
+    pub nintendo_logo: Vec<u8>,
+    pub title: String,
+    // 4 characters at 0x13F, which only later cartridges have.
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: CgbFlag,
+    pub sgb_flag: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub destination: Destination,
+    pub licensee_code: LicenseeCode,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub expected_header_checksum: u8,
+    pub global_checksum: u16,
+    pub expected_global_checksum: u16
+}
+
+impl Default for CartridgeHeader {
+    fn default() -> Self {
+        CartridgeHeader {
+            nintendo_logo: Vec::new(),
+            title: String::new(),
+            manufacturer_code: None,
+            cgb_flag: CgbFlag::DmgOnly,
+            sgb_flag: false,
+            cartridge_type: CartridgeType::RomOnly,
+            rom_size_code: 0x0,
+            ram_size_code: 0x0,
+            destination: Destination::Japan,
+            licensee_code: LicenseeCode::Old(0x0),
+            version: 0x0,
+            header_checksum: 0x0,
+            expected_header_checksum: 0x0,
+            global_checksum: 0x0,
+            expected_global_checksum: 0x0
+        }
+    }
+}
+
+// The title's length depends on the era: 16 bytes originally, then 15 once
+// 0x143 became the CGB flag, then 11 once 0x13F-0x142 held a manufacturer code.
+// Whatever's left over is padded, usually with NULs.
+fn parse_title(rom: &[u8], cgb_flag: CgbFlag, has_manufacturer_code: bool) -> String {
+    let end = match (cgb_flag, has_manufacturer_code) {
+        (CgbFlag::DmgOnly, _) => 0x143,
+        (_, false) => 0x142,
+        (_, true) => 0x13E
+    };
+    let title = &rom[0x134 .. end+1];
+    let len = title.iter().position(|&c| c == 0x00).unwrap_or(title.len());
+    // Non-ASCII bytes show up in some Japanese titles, and shouldn't stop anything loading.
+    String::from_utf8_lossy(&title[.. len]).trim_end().to_string()
+}
+
+impl CartridgeHeader {
+    pub fn from_rom(rom: &[u8]) -> Self {
+        let cgb_flag = match get_u8(rom, 0x143) {
+            0x80 => CgbFlag::Supported,
+            0xC0 => CgbFlag::Required,
+            _ => CgbFlag::DmgOnly
+        };
+        let manufacturer_code = &rom[0x13F .. 0x142+1];
+        let has_manufacturer_code = cgb_flag != CgbFlag::DmgOnly
+            && manufacturer_code.iter().all(|&c| (c as char).is_ascii_uppercase() || (c as char).is_ascii_digit());
+        CartridgeHeader {
+            nintendo_logo: rom[0x104 .. 0x133+1].to_vec(),
+            title: parse_title(rom, cgb_flag, has_manufacturer_code),
+            manufacturer_code: if has_manufacturer_code {
+                Some(String::from_utf8_lossy(manufacturer_code).into_owned())
+            } else {
+                None
+            },
+            cgb_flag: cgb_flag,
+            sgb_flag: get_u8(rom, 0x146) == 0x03,
+            cartridge_type: CartridgeType::from_code(get_u8(rom, 0x147)),
+            rom_size_code: get_u8(rom, 0x148),
+            ram_size_code: get_u8(rom, 0x149),
+            destination: match get_u8(rom, 0x14A) {
+                0x00 => Destination::Japan,
+                0x01 => Destination::Overseas,
+                code => Destination::Unknown(code)
+            },
+            licensee_code: LicenseeCode::from_rom(rom),
+            version: get_u8(rom, 0x14C),
+            header_checksum: get_u8(rom, 0x14D),
+            expected_header_checksum: header_checksum(rom),
+            // Unlike everything else, this one's big-endian.
+            global_checksum: u16_from_2u8s((get_u8(rom, 0x14F), get_u8(rom, 0x14E))),
+            expected_global_checksum: global_checksum(rom)
+        }
+    }
+
+    pub fn rom_size(&self) -> Option<usize> {
+        rom_size(self.rom_size_code)
+    }
+
+    pub fn ram_size(&self) -> Option<usize> {
+        header_ram_size(self.ram_size_code)
+    }
+}
+
+fn size_string(size: Option<usize>) -> String {
+    match size {
+        Some(0) => "None".to_string(),
+        Some(size) if size >= 0x100000 && size % 0x100000 == 0 => format!("{} MiB", size / 0x100000),
+        Some(size) => format!("{} KiB", size / 0x400),
+        None => "Unknown".to_string()
+    }
+}
+
+fn checksum_status(matches: bool) -> &'static str {
+    if matches { "OK" } else { "BAD" }
+}
+
+// A report along the lines of rgbfix -v or gbhdr.
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "Title:              {}", self.title));
+        try!(writeln!(f, "Manufacturer code:  {}", self.manufacturer_code.as_ref().map_or("None", |code| code.as_str())));
+        try!(writeln!(f, "CGB flag:           {}", match self.cgb_flag {
+            CgbFlag::DmgOnly => "DMG only",
+            CgbFlag::Supported => "CGB supported",
+            CgbFlag::Required => "CGB only"
+        }));
+        try!(writeln!(f, "SGB flag:           {}", if self.sgb_flag { "Supported" } else { "Not supported" }));
+        try!(writeln!(f, "Cartridge type:     {} ({:02X})", self.cartridge_type.name(), self.cartridge_type.code()));
+        try!(writeln!(f, "ROM size:           {} ({:02X})", size_string(self.rom_size()), self.rom_size_code));
+        try!(writeln!(f, "RAM size:           {} ({:02X})", size_string(self.ram_size()), self.ram_size_code));
+        try!(writeln!(f, "Destination:        {}", match self.destination {
+            Destination::Japan => "Japan".to_string(),
+            Destination::Overseas => "Overseas".to_string(),
+            Destination::Unknown(code) => format!("Unknown ({:02X})", code)
+        }));
+        try!(writeln!(f, "Licensee:           {} ({})", self.licensee_code.name().unwrap_or("Unknown"), match self.licensee_code {
+            LicenseeCode::Old(code) => format!("{:02X}", code),
+            LicenseeCode::New(code) => String::from_utf8_lossy(&code).into_owned()
+        }));
+        try!(writeln!(f, "Version:            {:02X}", self.version));
+        try!(writeln!(
+            f,
+            "Header checksum:    {:02X} ({}, expected {:02X})",
+            self.header_checksum,
+            checksum_status(self.header_checksum == self.expected_header_checksum),
+            self.expected_header_checksum
+        ));
+        write!(
+            f,
+            "Global checksum:    {:04X} ({}, expected {:04X})",
+            self.global_checksum,
+            checksum_status(self.global_checksum == self.expected_global_checksum),
+            self.expected_global_checksum
+        )
+    }
+}
+
+// What the boot ROM checks 0x14D against, computed over 0x134-0x14C.
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134 .. 0x14C+1].iter().fold(0u8, |x, &byte| x.wrapping_sub(byte).wrapping_sub(1))
+}
+
+// The big-endian sum at 0x14E covers every byte except itself. Nothing
+// actually checks it, so plenty of ROMs get it wrong.
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 0x14E && i != 0x14F)
+        .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A blank ROM with the title area filled from 0x134, and 0x143 and the
+    // manufacturer code at 0x13F-0x142 only set when given.
+    fn rom_with_title(title: &[u8], manufacturer_code: Option<&[u8]>, cgb_flag: Option<u8>) -> Vec<u8> {
+        let mut rom = vec![0x0; 0x8000];
+        rom[0x134 .. 0x134 + title.len()].copy_from_slice(title);
+        if let Some(code) = manufacturer_code {
+            rom[0x13F .. 0x142+1].copy_from_slice(code);
+        }
+        if let Some(flag) = cgb_flag {
+            rom[0x143] = flag;
+        }
+        rom
+    }
+
+    #[test]
+    fn dmg_title_uses_all_16_bytes() {
+        let rom = rom_with_title(b"SIXTEEN BYTES!!!", None, None);
+        let header = CartridgeHeader::from_rom(&rom);
+        assert_eq!(header.cgb_flag, CgbFlag::DmgOnly);
+        assert_eq!(header.title, "SIXTEEN BYTES!!!");
+        assert_eq!(header.manufacturer_code, None);
+    }
+
+    #[test]
+    fn cgb_title_stops_before_the_flag() {
+        // 0x13F-0x142 hold "ES!!", which can't be a manufacturer code, so all 15 bytes are title.
+        let rom = rom_with_title(b"FIFTEEN BYTES!!", None, Some(0x80));
+        let header = CartridgeHeader::from_rom(&rom);
+        assert_eq!(header.cgb_flag, CgbFlag::Supported);
+        assert_eq!(header.title, "FIFTEEN BYTES!!");
+        assert_eq!(header.manufacturer_code, None);
+    }
+
+    #[test]
+    fn cgb_title_stops_before_the_manufacturer_code() {
+        let rom = rom_with_title(b"PM_CRYSTAL", Some(b"BYTE"), Some(0xC0));
+        let header = CartridgeHeader::from_rom(&rom);
+        assert_eq!(header.cgb_flag, CgbFlag::Required);
+        assert_eq!(header.title, "PM_CRYSTAL");
+        assert_eq!(header.manufacturer_code, Some("BYTE".to_string()));
+    }
+
+    #[test]
+    fn title_padding_is_stripped() {
+        let rom = rom_with_title(b"TETRIS\0\0\0JUNK", None, None);
+        assert_eq!(CartridgeHeader::from_rom(&rom).title, "TETRIS");
+        let rom = rom_with_title(b"TETRIS          ", None, None);
+        assert_eq!(CartridgeHeader::from_rom(&rom).title, "TETRIS");
+    }
+
+    #[test]
+    fn invalid_utf8_in_title_is_replaced() {
+        let rom = rom_with_title(b"POKE\xB1\xCFON", None, None);
+        assert_eq!(CartridgeHeader::from_rom(&rom).title, "POKE\u{FFFD}\u{FFFD}ON");
+    }
+}
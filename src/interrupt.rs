@@ -0,0 +1,88 @@
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad
+}
+
+// Ordered by priority, highest first.
+pub const INTERRUPTS: [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::LcdStat,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::Joypad
+];
+
+impl Interrupt {
+    // Bit position in both IE (0xFFFF) and IF (0xFF0F).
+    pub fn mask(&self) -> u8 {
+        match *self {
+            Interrupt::VBlank => 0b0000_0001u8,
+            Interrupt::LcdStat => 0b0000_0010u8,
+            Interrupt::Timer => 0b0000_0100u8,
+            Interrupt::Serial => 0b0000_1000u8,
+            Interrupt::Joypad => 0b0001_0000u8
+        }
+    }
+
+    pub fn vector(&self) -> u16 {
+        match *self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::LcdStat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60
+        }
+    }
+}
+
+// Owns IE (0xFFFF) and IF (0xFF0F).
+#[derive(Default)]
+pub struct Interrupts {
+    enable: u8,
+    flag: u8
+}
+
+impl Interrupts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read_enable(&self) -> u8 {
+        self.enable
+    }
+
+    pub fn write_enable(&mut self, value: u8) {
+        self.enable = value;
+    }
+
+    pub fn read_flag(&self) -> u8 {
+        // Only the low 5 bits of IF exist; the rest read back as 1.
+        0b1110_0000u8 | self.flag
+    }
+
+    pub fn write_flag(&mut self, value: u8) {
+        self.flag = value & 0b0001_1111u8;
+    }
+
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.flag |= interrupt.mask();
+    }
+
+    pub fn acknowledge(&mut self, interrupt: Interrupt) {
+        self.flag &= !interrupt.mask();
+    }
+
+    pub fn is_requested(&self, interrupt: Interrupt) -> bool {
+        self.flag & interrupt.mask() != 0
+    }
+
+    // The highest priority interrupt that's both requested and enabled, if any.
+    pub fn pending(&self) -> Option<Interrupt> {
+        let pending = self.enable & self.flag;
+        INTERRUPTS.iter().cloned().find(|interrupt| pending & interrupt.mask() != 0)
+    }
+}
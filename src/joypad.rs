@@ -0,0 +1,92 @@
+use interrupt::*;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start
+}
+
+impl Button {
+    // Bit within pressed; the low nibble is the d-pad, and the high one the buttons.
+    fn mask(&self) -> u8 {
+        match *self {
+            Button::Right => 0b0000_0001u8,
+            Button::Left => 0b0000_0010u8,
+            Button::Up => 0b0000_0100u8,
+            Button::Down => 0b0000_1000u8,
+            Button::A => 0b0001_0000u8,
+            Button::B => 0b0010_0000u8,
+            Button::Select => 0b0100_0000u8,
+            Button::Start => 0b1000_0000u8
+        }
+    }
+}
+
+// Owns P1/JOYP (0xFF00).
+pub struct Joypad {
+    // P14/P15, the only writable bits. Pulling P14 low selects the d-pad, and
+    // pulling P15 low selects the buttons.
+    select: u8,
+    pressed: u8
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad {
+            select: 0b0011_0000u8,
+            pressed: 0x0
+        }
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // P10-P13, which are active-low and shared by whichever groups are selected.
+    fn input_lines(&self) -> u8 {
+        let mut lines = 0b0000_1111u8;
+        if self.select & 0b0001_0000u8 == 0 {
+            lines &= !(self.pressed & 0b0000_1111u8);
+        }
+        if self.select & 0b0010_0000u8 == 0 {
+            lines &= !(self.pressed >> 4);
+        }
+        lines
+    }
+
+    pub fn read(&self) -> u8 {
+        // Bits 6-7 are unused and read as 1.
+        0b1100_0000u8 | self.select | self.input_lines()
+    }
+
+    // The joypad interrupt fires when any input line goes from high to low.
+    fn update_lines<F: FnOnce(&mut Self)>(&mut self, interrupts: &mut Interrupts, change: F) {
+        let before = self.input_lines();
+        change(self);
+        if before & !self.input_lines() != 0 {
+            interrupts.request(Interrupt::Joypad);
+        }
+    }
+
+    pub fn write(&mut self, value: u8, interrupts: &mut Interrupts) {
+        self.update_lines(interrupts, |joypad| joypad.select = value & 0b0011_0000u8);
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool, interrupts: &mut Interrupts) {
+        self.update_lines(interrupts, |joypad| {
+            if pressed {
+                joypad.pressed |= button.mask();
+            } else {
+                joypad.pressed &= !button.mask();
+            }
+        });
+    }
+}
@@ -7,18 +7,107 @@ mod util;
 mod gameboy;
 mod cpu;
 mod mem;
+mod interrupt;
+mod mbc;
+mod cartridge;
+mod joypad;
+mod serial;
+mod timer;
+mod apu;
+mod ppu;
+mod header;
 
+use std::env;
+use std::rc::Rc;
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
 use util::*;
 use gameboy::*;
+use joypad::*;
+use ppu::*;
+use apu::DEFAULT_SAMPLE_RATE;
 
-fn main() {
-    let mut gameboy = GameBoy::new();
-    let load_result = gameboy.load_rom("roms/blue.gb");
-    println!("== {} ==", gameboy.game_title);
-    if let Err(e) = load_result {
-        match e {
-            CartridgeValidationError::InvalidNintendoLogo => {
-                println!(
+// A frame is 70224 cycles at 4194304 Hz, which is about 59.73 frames a second.
+const FRAME_DURATION_NANOS: u32 = 16_742_706;
+const DEFAULT_SCALE: u32 = 4;
+// Interleaved stereo samples pulled from the APU at a time.
+const AUDIO_CHUNK_SAMPLES: usize = 2048;
+// About a tenth of a second of 44.1 kHz stereo f32, past which samples get
+// dropped instead of adding latency.
+const MAX_QUEUED_AUDIO_BYTES: u32 = 35_280;
+// RGB for each shade, from lightest to darkest.
+const SHADES: [[u8; 3]; 4] = [
+    [0xE0, 0xF8, 0xD0],
+    [0x88, 0xC0, 0x70],
+    [0x34, 0x68, 0x56],
+    [0x08, 0x18, 0x20]
+];
+
+fn button_for_key(keycode: Keycode) -> Option<Button> {
+    match keycode {
+        Keycode::Right => Some(Button::Right),
+        Keycode::Left => Some(Button::Left),
+        Keycode::Up => Some(Button::Up),
+        Keycode::Down => Some(Button::Down),
+        Keycode::X => Some(Button::A),
+        Keycode::Z => Some(Button::B),
+        Keycode::Backspace => Some(Button::Select),
+        Keycode::Return => Some(Button::Start),
+        _ => None
+    }
+}
+
+// The biggest whole-number scale of the screen that fits the window, centered.
+fn scaled_screen_rect(window_width: u32, window_height: u32) -> Rect {
+    let scale = (window_width / SCREEN_WIDTH as u32)
+        .min(window_height / SCREEN_HEIGHT as u32)
+        .max(1);
+    let width = SCREEN_WIDTH as u32 * scale;
+    let height = SCREEN_HEIGHT as u32 * scale;
+    let x = (window_width as i32 - width as i32) / 2;
+    let y = (window_height as i32 - height as i32) / 2;
+    Rect::new(x, y, width, height)
+}
+
+// Sound is optional, so failing to open a device just means playing silently.
+fn open_audio(sdl_context: &sdl2::Sdl) -> Option<AudioQueue<f32>> {
+    let desired = AudioSpecDesired {
+        freq: Some(DEFAULT_SAMPLE_RATE as i32),
+        channels: Some(2),
+        samples: Some(1024)
+    };
+    let result = sdl_context.audio().and_then(|audio| audio.open_queue(None, &desired));
+    match result {
+        Ok(queue) => Some(queue),
+        Err(e) => {
+            println!("[Warning] Failed to open audio device: {}", e);
+            None
+        }
+    }
+}
+
+fn queue_audio(gameboy: &mut GameBoy, queue: &AudioQueue<f32>, samples: &mut [f32]) {
+    loop {
+        let count = gameboy.pull_samples(samples);
+        if count == 0 {
+            break;
+        }
+        if queue.size() < MAX_QUEUED_AUDIO_BYTES {
+            queue.queue(&samples[.. count]);
+        }
+    }
+}
+
+fn print_warning(gameboy: &GameBoy, warning: &CartridgeValidationError) {
+    match *warning {
+        CartridgeValidationError::InvalidNintendoLogo => {
+            println!(
 r#"[Warning 00] Nintendo logo (0x104 .. 0x133) did not match expected value.
     Expected:
         CE ED 66 66 CC 0D 00 0B 03 73 00 83 00 0C 00 0D
@@ -27,11 +116,143 @@ r#"[Warning 00] Nintendo logo (0x104 .. 0x133) did not match expected value.
     Found:
         {}
     This ROM will not run on a real Game Boy."#,
-                    hexdump_slice(&gameboy.rom[0x104 .. 0x133+1])
-                );
-            },
-            _ => println!("Unhandled error event.")
+                hexdump_slice(&gameboy.header().nintendo_logo)
+            );
+        },
+        _ => println!("[Warning] {}", warning)
+    }
+}
+
+fn main() {
+    // game-girl [--info] [--fifo] [rom], where --info prints the header report
+    // and quits, and --fifo picks the slower pixel FIFO renderer.
+    let args: Vec<String> = env::args().skip(1).collect();
+    let info_only = args.iter().any(|arg| arg == "--info");
+    let ppu_renderer = if args.iter().any(|arg| arg == "--fifo") {
+        Renderer::PixelFifo
+    } else {
+        Renderer::Scanline
+    };
+    let rom_path = args.iter()
+        .find(|arg| !arg.starts_with("--"))
+        .map_or("roms/blue.gb", |arg| arg.as_str());
+
+    let mut gameboy = GameBoy::new();
+    gameboy.set_renderer(ppu_renderer);
+    let load_result = gameboy.load_rom(rom_path);
+    {
+        // Cartridges that can't be emulated still have a header worth showing.
+        let header = match load_result {
+            Ok(_) => Some(gameboy.header()),
+            Err(GameBoyError::UnsupportedMapper(ref header)) |
+            Err(GameBoyError::InvalidCartridge(ref header, _)) => Some(header),
+            Err(_) => None
+        };
+        if let Some(header) = header {
+            if info_only {
+                println!("{}", header);
+            } else {
+                println!("== {} ==", header.title);
+            }
+        }
+    }
+    let warnings = match load_result {
+        Ok(warnings) => warnings,
+        Err(e) => {
+            println!("[Error] {}", e);
+            return;
         }
     };
-    gameboy.run()
+    for warning in &warnings {
+        print_warning(&gameboy, warning);
+    }
+    if info_only {
+        return;
+    }
+
+    let sdl_context = sdl2::init().unwrap();
+    let video = sdl_context.video().unwrap();
+    let window = video.window(
+        &format!("game-girl - {}", gameboy.header().title),
+        SCREEN_WIDTH as u32 * DEFAULT_SCALE,
+        SCREEN_HEIGHT as u32 * DEFAULT_SCALE
+    )
+        .position_centered()
+        .resizable()
+        .build()
+        .unwrap();
+    let mut renderer = window.renderer().accelerated().build().unwrap();
+    let mut texture = renderer.create_texture_streaming(
+        PixelFormatEnum::RGB24,
+        SCREEN_WIDTH as u32,
+        SCREEN_HEIGHT as u32
+    ).unwrap();
+    let mut pixels = vec![0x0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let audio_queue = open_audio(&sdl_context);
+    if let Some(ref queue) = audio_queue {
+        // The device might not have been able to give us the rate we asked for.
+        gameboy.set_sample_rate(queue.spec().freq as u32);
+        queue.resume();
+    }
+    let mut samples = vec![0.0; AUDIO_CHUNK_SAMPLES];
+    // There's no motor to drive, so rumble just gets logged. Games pulse it
+    // rapidly, so it's only checked once a frame.
+    let rumbling = Rc::new(Cell::new(false));
+    let callback_rumbling = rumbling.clone();
+    gameboy.set_rumble_callback(move |on| callback_rumbling.set(on));
+    let mut was_rumbling = false;
+
+    let frame_duration = Duration::new(0, FRAME_DURATION_NANOS);
+    let mut next_frame = Instant::now() + frame_duration;
+    println!("Beginning execution.");
+    'running: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    break 'running;
+                },
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                    if let Some(button) = button_for_key(keycode) {
+                        gameboy.set_button(button, true);
+                    }
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(button) = button_for_key(keycode) {
+                        gameboy.set_button(button, false);
+                    }
+                },
+                _ => ()
+            }
+        }
+        gameboy.run_frame();
+        if let Some(ref queue) = audio_queue {
+            queue_audio(&mut gameboy, queue, &mut samples);
+        }
+        if rumbling.get() != was_rumbling {
+            was_rumbling = rumbling.get();
+            println!("[Rumble] {}", if was_rumbling { "On" } else { "Off" });
+        }
+
+        for (pixel, &shade) in pixels.chunks_mut(3).zip(gameboy.framebuffer()) {
+            pixel.copy_from_slice(&SHADES[shade as usize]);
+        }
+        texture.update(None, &pixels, SCREEN_WIDTH * 3).unwrap();
+        let (window_width, window_height) = renderer.output_size().unwrap();
+        renderer.clear();
+        renderer.copy(&texture, None, Some(scaled_screen_rect(window_width, window_height))).unwrap();
+        renderer.present();
+
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+            next_frame += frame_duration;
+        } else {
+            // Running behind, so don't try to catch up by racing through frames.
+            next_frame = now + frame_duration;
+        }
+    }
+
+    // Games don't always disable RAM after saving, so don't rely on that here.
+    gameboy.save();
 }
@@ -0,0 +1,149 @@
+use mbc::*;
+
+pub struct Mbc1 {
+    ram_enabled: bool,
+    // BANK1, the low 5 bits of the ROM bank number.
+    rom_bank_low: u8,
+    // BANK2, either the upper 2 bits of the ROM bank number or the RAM bank number.
+    bank_high: u8,
+    // In mode 1, BANK2 also applies to 0x0000-0x3FFF and to RAM.
+    advanced_banking: bool
+}
+
+impl Default for Mbc1 {
+    fn default() -> Self {
+        Mbc1 {
+            ram_enabled: false,
+            rom_bank_low: 0x1,
+            bank_high: 0x0,
+            advanced_banking: false
+        }
+    }
+}
+
+impl Mbc1 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn write_control(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000 ... 0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000 ... 0x3FFF => {
+                // Bank 0 can't be selected here, but the check only looks at these 5 bits,
+                // which is why banks 0x20, 0x40 and 0x60 end up as 0x21, 0x41 and 0x61.
+                self.rom_bank_low = match value & 0b0001_1111u8 {
+                    0 => 1,
+                    bank => bank
+                };
+            },
+            0x4000 ... 0x5FFF => self.bank_high = value & 0b0000_0011u8,
+            0x6000 ... 0x7FFF => self.advanced_banking = value & 0b0000_0001u8 != 0,
+            _ => unreachable!("MBC1 registers only span 0x0000-0x7FFF.")
+        }
+    }
+
+    fn rom_bank0(&self) -> usize {
+        if self.advanced_banking {
+            (self.bank_high as usize) << 5
+        } else {
+            0
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        (self.bank_high as usize) << 5 | self.rom_bank_low as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.advanced_banking {
+            self.bank_high as usize
+        } else {
+            0
+        }
+    }
+
+    fn is_ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mem::*;
+    use cartridge::*;
+
+    // A 2 MiB MBC1 cartridge with 32 KiB of RAM, where the first byte of each
+    // ROM bank holds its bank number.
+    fn mem_with_mbc1() -> Mem {
+        let mut rom = vec![0x0; 0x200000];
+        for bank in 0 .. 0x80 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x147] = 0x03;
+        rom[0x148] = 0x06;
+        rom[0x149] = 0x03;
+        let mut mem = Mem::new();
+        mem.insert_cartridge(new_cartridge(rom).ok().unwrap());
+        mem
+    }
+
+    #[test]
+    fn bank_0_selects_bank_1() {
+        let mut mem = mem_with_mbc1();
+        assert_eq!(mem.read_u8(0x4000), 0x01);
+        mem.write_u8(0x2000, 0x05);
+        assert_eq!(mem.read_u8(0x4000), 0x05);
+        mem.write_u8(0x2000, 0x00);
+        assert_eq!(mem.read_u8(0x4000), 0x01);
+    }
+
+    #[test]
+    fn banks_20_40_and_60_select_the_next_bank() {
+        let mut mem = mem_with_mbc1();
+        mem.write_u8(0x2000, 0x00);
+        for &(bank_high, expected) in [(1, 0x21), (2, 0x41), (3, 0x61)].iter() {
+            mem.write_u8(0x4000, bank_high);
+            assert_eq!(mem.read_u8(0x4000), expected);
+        }
+        // Only the low 5 bits reach BANK1, so 0x20 is treated as 0 as well.
+        mem.write_u8(0x4000, 0x00);
+        mem.write_u8(0x2000, 0x20);
+        assert_eq!(mem.read_u8(0x4000), 0x01);
+    }
+
+    #[test]
+    fn mode_1_applies_bank_2_to_rom_bank_0() {
+        let mut mem = mem_with_mbc1();
+        mem.write_u8(0x4000, 0x02);
+        assert_eq!(mem.read_u8(0x0000), 0x00);
+        mem.write_u8(0x6000, 0x01);
+        assert_eq!(mem.read_u8(0x0000), 0x40);
+        assert_eq!(mem.read_u8(0x4000), 0x41);
+        mem.write_u8(0x6000, 0x00);
+        assert_eq!(mem.read_u8(0x0000), 0x00);
+    }
+
+    #[test]
+    fn ram_banks_only_switch_in_mode_1() {
+        let mut mem = mem_with_mbc1();
+        // RAM stays disabled until 0x0A is written to 0x0000-0x1FFF.
+        mem.write_u8(0xA000, 0x12);
+        assert_eq!(mem.read_u8(0xA000), 0xFF);
+        mem.write_u8(0x0000, 0x0A);
+        mem.write_u8(0xA000, 0x12);
+        mem.write_u8(0x6000, 0x01);
+        mem.write_u8(0x4000, 0x02);
+        assert_eq!(mem.read_u8(0xA000), 0x00);
+        mem.write_u8(0xA000, 0x34);
+        // Back in mode 0, bank 0 is mapped no matter what BANK2 holds.
+        mem.write_u8(0x6000, 0x00);
+        assert_eq!(mem.read_u8(0xA000), 0x12);
+        mem.write_u8(0x6000, 0x01);
+        assert_eq!(mem.read_u8(0xA000), 0x34);
+        mem.write_u8(0x0000, 0x00);
+        assert_eq!(mem.read_u8(0xA000), 0xFF);
+    }
+}
@@ -0,0 +1,135 @@
+use mbc::*;
+
+// MBC2 has 512 half-bytes of RAM built into the controller itself.
+const MBC2_RAM_SIZE: usize = 0x200;
+
+pub struct Mbc2 {
+    ram_enabled: bool,
+    rom_bank: u8
+}
+
+impl Default for Mbc2 {
+    fn default() -> Self {
+        Mbc2 {
+            ram_enabled: false,
+            rom_bank: 0x1
+        }
+    }
+}
+
+impl Mbc2 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn write_control(&mut self, addr: u16, value: u8) {
+        match addr {
+            // Both registers live in 0x0000-0x3FFF; bit 8 of the address picks which one.
+            0x0000 ... 0x3FFF => {
+                if addr & 0x0100 == 0 {
+                    self.ram_enabled = value & 0x0F == 0x0A;
+                } else {
+                    self.rom_bank = match value & 0x0F {
+                        0 => 1,
+                        bank => bank
+                    };
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn is_ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn ram_size(&self, _header_ram_size: usize) -> usize {
+        MBC2_RAM_SIZE
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        // Only the low nibble is stored, and the rest of 0xA000-0xBFFF mirrors it.
+        0xF0 | ram[(addr as usize - 0xA000) % MBC2_RAM_SIZE]
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if self.ram_enabled {
+            ram[(addr as usize - 0xA000) % MBC2_RAM_SIZE] = value & 0x0F;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mem::*;
+    use cartridge::*;
+
+    // A 256 KiB MBC2 cartridge, where the first byte of each ROM bank holds its
+    // bank number.
+    fn mem_with_mbc2() -> Mem {
+        let mut rom = vec![0x0; 0x40000];
+        for bank in 0 .. 0x10 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x147] = 0x06;
+        rom[0x148] = 0x03;
+        let mut mem = Mem::new();
+        mem.insert_cartridge(new_cartridge(rom).ok().unwrap());
+        mem
+    }
+
+    #[test]
+    fn address_bit_8_selects_the_register() {
+        let mut mem = mem_with_mbc2();
+        // Bit 8 clear is RAM enable, even for a value that looks like a bank.
+        mem.write_u8(0x2000, 0x05);
+        assert_eq!(mem.read_u8(0x4000), 0x01);
+        mem.write_u8(0x2100, 0x05);
+        assert_eq!(mem.read_u8(0x4000), 0x05);
+        // Bit 8 set is the ROM bank, even down at 0x0000-0x1FFF.
+        mem.write_u8(0x0100, 0x0A);
+        assert_eq!(mem.read_u8(0x4000), 0x0A);
+        mem.write_u8(0xA000, 0x01);
+        assert_eq!(mem.read_u8(0xA000), 0xFF);
+        mem.write_u8(0x3EFF, 0x0A);
+        mem.write_u8(0xA000, 0x01);
+        assert_eq!(mem.read_u8(0xA000), 0xF1);
+    }
+
+    #[test]
+    fn rom_bank_0_selects_bank_1() {
+        let mut mem = mem_with_mbc2();
+        mem.write_u8(0x2100, 0x00);
+        assert_eq!(mem.read_u8(0x4000), 0x01);
+        // Only the low 4 bits count, so 0x10 is bank 0 as well.
+        mem.write_u8(0x2100, 0x10);
+        assert_eq!(mem.read_u8(0x4000), 0x01);
+        mem.write_u8(0x2100, 0x1F);
+        assert_eq!(mem.read_u8(0x4000), 0x0F);
+    }
+
+    #[test]
+    fn ram_is_512_nibbles_mirrored_across_the_range() {
+        let mut mem = mem_with_mbc2();
+        mem.write_u8(0x0000, 0x0A);
+        mem.write_u8(0xA000, 0xAB);
+        mem.write_u8(0xA1FF, 0x3C);
+        // The upper nibble isn't stored, and reads back as 1s.
+        assert_eq!(mem.read_u8(0xA000), 0xFB);
+        assert_eq!(mem.read_u8(0xA1FF), 0xFC);
+        assert_eq!(mem.read_u8(0xA200), 0xFB);
+        assert_eq!(mem.read_u8(0xBFFF), 0xFC);
+        mem.write_u8(0xBE00, 0x07);
+        assert_eq!(mem.read_u8(0xA000), 0xF7);
+        assert_eq!(mem.save_ram().len(), 0x200);
+    }
+}
@@ -0,0 +1,403 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use util::*;
+use mbc::*;
+
+// The RTC is clocked by its own 32.768 KHz crystal, but that divides evenly into the
+// CPU clock, so emulated time can just be counted in T-cycles.
+const CYCLES_PER_SECOND: u32 = 4194304;
+
+// Size of the RTC footer appended to the save file, in the same layout VBA and BGB use.
+const RTC_SAVE_SIZE: usize = 48;
+
+#[derive(Copy, Clone, Default)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    // 9-bit day counter.
+    days: u16,
+    halted: bool,
+    day_carry: bool
+}
+
+impl RtcRegisters {
+    fn read(&self, select: u8) -> u8 {
+        match select {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days as u8,
+            0x0C => {
+                (self.days >> 8) as u8 & 0b0000_0001u8
+                    | (self.halted as u8) << 6
+                    | (self.day_carry as u8) << 7
+            },
+            _ => unreachable!("RTC registers only span 0x08-0x0C.")
+        }
+    }
+
+    fn write(&mut self, select: u8, value: u8) {
+        match select {
+            0x08 => self.seconds = value & 0b0011_1111u8,
+            0x09 => self.minutes = value & 0b0011_1111u8,
+            0x0A => self.hours = value & 0b0001_1111u8,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | ((value as u16 & 0b0000_0001u16) << 8);
+                self.halted = value & 0b0100_0000u8 != 0;
+                self.day_carry = value & 0b1000_0000u8 != 0;
+            },
+            _ => unreachable!("RTC registers only span 0x08-0x0C.")
+        }
+    }
+
+    fn advance_second(&mut self) {
+        // Each counter only carries when it hits its nominal limit, so out of range
+        // values written by the game have to wrap all the way around their bit width.
+        self.seconds = (self.seconds + 1) & 0b0011_1111u8;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0b0011_1111u8;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0b0001_1111u8;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.advance_days(1);
+    }
+
+    fn advance_days(&mut self, days: u64) {
+        let days = self.days as u64 + days;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (days & 0x1FF) as u16;
+    }
+
+    // Catches up on time that passed while the emulator wasn't running.
+    fn advance_seconds(&mut self, seconds: u64) {
+        if self.halted {
+            return;
+        }
+        let mut seconds = seconds;
+        // Let out of range values wrap the slow way first.
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.advance_second();
+            seconds -= 1;
+        }
+        let total = seconds
+            + self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 60 * 60;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / (60 * 60) % 24) as u8;
+        self.advance_days(total / (60 * 60 * 24));
+    }
+}
+
+pub struct Mbc3 {
+    ram_enabled: bool,
+    rom_bank: u8,
+    // 0x00-0x03 selects a RAM bank, 0x08-0x0C selects an RTC register.
+    ram_bank_or_rtc: u8,
+    has_rtc: bool,
+    rtc: RtcRegisters,
+    latched_rtc: RtcRegisters,
+    last_latch_write: u8,
+    rtc_cycles: u32
+}
+
+impl Mbc3 {
+    pub fn new(has_rtc: bool) -> Self {
+        Mbc3 {
+            ram_enabled: false,
+            rom_bank: 0x1,
+            ram_bank_or_rtc: 0x0,
+            has_rtc: has_rtc,
+            rtc: RtcRegisters::default(),
+            latched_rtc: RtcRegisters::default(),
+            last_latch_write: 0xFF,
+            rtc_cycles: 0
+        }
+    }
+
+    fn selected_rtc_register(&self) -> Option<u8> {
+        match self.ram_bank_or_rtc {
+            0x08 ... 0x0C if self.has_rtc => Some(self.ram_bank_or_rtc),
+            _ => None
+        }
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn write_control(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000 ... 0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000 ... 0x3FFF => {
+                self.rom_bank = match value & 0b0111_1111u8 {
+                    0 => 1,
+                    bank => bank
+                };
+            },
+            0x4000 ... 0x5FFF => self.ram_bank_or_rtc = value,
+            0x6000 ... 0x7FFF => {
+                // Writing 0x00 then 0x01 copies the live clock into the readable registers.
+                if self.last_latch_write == 0x00 && value == 0x01 {
+                    self.latched_rtc = self.rtc;
+                }
+                self.last_latch_write = value;
+            },
+            _ => unreachable!("MBC3 registers only span 0x0000-0x7FFF.")
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        (self.ram_bank_or_rtc & 0b0000_0011u8) as usize
+    }
+
+    fn is_ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match self.selected_rtc_register() {
+            Some(select) => self.latched_rtc.read(select),
+            None if self.ram_bank_or_rtc <= 0x03 && !ram.is_empty() => {
+                ram[ram_offset(ram, self.ram_bank(), addr)]
+            },
+            None => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        match self.selected_rtc_register() {
+            Some(select) => {
+                if select == 0x08 {
+                    // Writing the seconds also resets the sub-second divider.
+                    self.rtc_cycles = 0;
+                }
+                self.rtc.write(select, value);
+                self.latched_rtc.write(select, value);
+            },
+            None if self.ram_bank_or_rtc <= 0x03 && !ram.is_empty() => {
+                let offset = ram_offset(ram, self.ram_bank(), addr);
+                ram[offset] = value;
+            },
+            None => ()
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if !self.has_rtc || self.rtc.halted {
+            return;
+        }
+        self.rtc_cycles += cycles;
+        while self.rtc_cycles >= CYCLES_PER_SECOND {
+            self.rtc_cycles -= CYCLES_PER_SECOND;
+            self.rtc.advance_second();
+        }
+    }
+
+    fn save_extra(&self) -> Vec<u8> {
+        if !self.has_rtc {
+            return Vec::new();
+        }
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        for regs in &[self.rtc, self.latched_rtc] {
+            for select in 0x08 .. 0x0C+1 {
+                let bytes = u32_to_4u8s(regs.read(select) as u32);
+                data.extend_from_slice(&[bytes.3, bytes.2, bytes.1, bytes.0]);
+            }
+        }
+        let bytes = u64_to_8u8s(unix_time());
+        data.extend_from_slice(&[
+            bytes.7, bytes.6, bytes.5, bytes.4, bytes.3, bytes.2, bytes.1, bytes.0
+        ]);
+        data
+    }
+
+    fn load_extra(&mut self, data: &[u8]) {
+        if !self.has_rtc || data.len() < RTC_SAVE_SIZE {
+            return;
+        }
+        for (i, select) in (0x08 .. 0x0C+1).enumerate() {
+            self.rtc.write(select, get_u32(data, i*4) as u8);
+            self.latched_rtc.write(select, get_u32(data, 20 + i*4) as u8);
+        }
+        let saved_at = get_u64(data, 40);
+        self.rtc.advance_seconds(unix_time().saturating_sub(saved_at));
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mem::*;
+    use cartridge::*;
+
+    // An MBC3 cartridge with an RTC and 32 KiB of RAM, with RAM enabled.
+    fn mem_with_mbc3() -> Mem {
+        let mut rom = vec![0x0; 0x8000];
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x03;
+        let mut mem = Mem::new();
+        mem.insert_cartridge(new_cartridge(rom).ok().unwrap());
+        mem.write_u8(0x0000, 0x0A);
+        mem
+    }
+
+    fn read_rtc(mem: &mut Mem, select: u8) -> u8 {
+        mem.write_u8(0x4000, select);
+        mem.read_u8(0xA000)
+    }
+
+    fn write_rtc(mem: &mut Mem, select: u8, value: u8) {
+        mem.write_u8(0x4000, select);
+        mem.write_u8(0xA000, value);
+    }
+
+    fn latch(mem: &mut Mem) {
+        mem.write_u8(0x6000, 0x00);
+        mem.write_u8(0x6000, 0x01);
+    }
+
+    #[test]
+    fn rtc_reads_only_change_when_latched() {
+        let mut mem = mem_with_mbc3();
+        mem.tick(CYCLES_PER_SECOND);
+        assert_eq!(read_rtc(&mut mem, 0x08), 0);
+        latch(&mut mem);
+        assert_eq!(read_rtc(&mut mem, 0x08), 1);
+        mem.tick(CYCLES_PER_SECOND);
+        assert_eq!(read_rtc(&mut mem, 0x08), 1);
+        // It takes a 0x00 write before each 0x01 to latch again.
+        mem.write_u8(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mem, 0x08), 1);
+        latch(&mut mem);
+        assert_eq!(read_rtc(&mut mem, 0x08), 2);
+    }
+
+    #[test]
+    fn halted_rtc_stops_counting() {
+        let mut mem = mem_with_mbc3();
+        write_rtc(&mut mem, 0x0C, 0x40);
+        mem.tick(CYCLES_PER_SECOND * 2);
+        latch(&mut mem);
+        assert_eq!(read_rtc(&mut mem, 0x08), 0);
+        assert_eq!(read_rtc(&mut mem, 0x0C), 0x40);
+        write_rtc(&mut mem, 0x0C, 0x00);
+        mem.tick(CYCLES_PER_SECOND);
+        latch(&mut mem);
+        assert_eq!(read_rtc(&mut mem, 0x08), 1);
+    }
+
+    #[test]
+    fn day_counter_overflow_sets_the_carry() {
+        let mut mem = mem_with_mbc3();
+        write_rtc(&mut mem, 0x08, 59);
+        write_rtc(&mut mem, 0x09, 59);
+        write_rtc(&mut mem, 0x0A, 23);
+        write_rtc(&mut mem, 0x0B, 0xFF);
+        write_rtc(&mut mem, 0x0C, 0x01);
+        mem.tick(CYCLES_PER_SECOND);
+        latch(&mut mem);
+        assert_eq!(read_rtc(&mut mem, 0x08), 0);
+        assert_eq!(read_rtc(&mut mem, 0x09), 0);
+        assert_eq!(read_rtc(&mut mem, 0x0A), 0);
+        assert_eq!(read_rtc(&mut mem, 0x0B), 0);
+        assert_eq!(read_rtc(&mut mem, 0x0C), 0x80);
+        // The carry sticks until it's written.
+        mem.tick(CYCLES_PER_SECOND);
+        latch(&mut mem);
+        assert_eq!(read_rtc(&mut mem, 0x0C), 0x80);
+        write_rtc(&mut mem, 0x0C, 0x00);
+        assert_eq!(read_rtc(&mut mem, 0x0C), 0x00);
+    }
+
+    #[test]
+    fn ram_bank_register_also_selects_rtc_registers() {
+        let mut mem = mem_with_mbc3();
+        mem.write_u8(0x4000, 0x00);
+        mem.write_u8(0xA000, 0x12);
+        mem.write_u8(0x4000, 0x03);
+        mem.write_u8(0xA000, 0x34);
+        write_rtc(&mut mem, 0x09, 0x05);
+        assert_eq!(read_rtc(&mut mem, 0x09), 0x05);
+        mem.write_u8(0x4000, 0x00);
+        assert_eq!(mem.read_u8(0xA000), 0x12);
+        mem.write_u8(0x4000, 0x03);
+        assert_eq!(mem.read_u8(0xA000), 0x34);
+        // Anything between the RAM banks and the RTC registers maps nothing.
+        mem.write_u8(0x4000, 0x05);
+        assert_eq!(mem.read_u8(0xA000), 0xFF);
+        // Disabling RAM hides the RTC as well.
+        mem.write_u8(0x0000, 0x00);
+        assert_eq!(read_rtc(&mut mem, 0x09), 0xFF);
+    }
+
+    #[test]
+    fn rtc_round_trips_through_the_save_footer() {
+        let mut mem = mem_with_mbc3();
+        mem.write_u8(0xA000, 0x12);
+        write_rtc(&mut mem, 0x08, 30);
+        write_rtc(&mut mem, 0x09, 20);
+        write_rtc(&mut mem, 0x0A, 10);
+        write_rtc(&mut mem, 0x0B, 0x34);
+        // Halted, so no time passes between saving and loading.
+        write_rtc(&mut mem, 0x0C, 0x41);
+        let save = mem.save_ram();
+        assert_eq!(save.len(), RAM_BANK_SIZE * 4 + RTC_SAVE_SIZE);
+        // Each register is a little-endian u32, live clock first.
+        assert_eq!(&save[RAM_BANK_SIZE * 4 .. RAM_BANK_SIZE * 4 + 8], &[30, 0, 0, 0, 20, 0, 0, 0]);
+
+        let mut mem = mem_with_mbc3();
+        mem.load_save_ram(&save);
+        assert_eq!(mem.read_u8(0xA000), 0x12);
+        latch(&mut mem);
+        let registers: Vec<u8> = (0x08 .. 0x0C+1).map(|select| read_rtc(&mut mem, select)).collect();
+        assert_eq!(registers, vec![30, 20, 10, 0x34, 0x41]);
+    }
+
+    #[test]
+    fn rtc_catches_up_on_time_since_saving() {
+        let mem = mem_with_mbc3();
+        let mut save = mem.save_ram();
+        // Pretend the save is an hour old.
+        let footer = save.len() - 8;
+        let saved_at = get_u64(&save, footer) - 60 * 60;
+        let bytes = u64_to_8u8s(saved_at);
+        save[footer ..].copy_from_slice(&[
+            bytes.7, bytes.6, bytes.5, bytes.4, bytes.3, bytes.2, bytes.1, bytes.0
+        ]);
+
+        let mut mem = mem_with_mbc3();
+        mem.load_save_ram(&save);
+        latch(&mut mem);
+        assert_eq!(read_rtc(&mut mem, 0x0A), 1);
+        assert_eq!(read_rtc(&mut mem, 0x09), 0);
+        assert!(read_rtc(&mut mem, 0x08) <= 1);
+    }
+}
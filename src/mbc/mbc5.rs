@@ -0,0 +1,138 @@
+use mbc::*;
+
+pub struct Mbc5 {
+    ram_enabled: bool,
+    // 9-bit ROM bank number; unlike the older MBCs, bank 0 can be mapped here too.
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool
+}
+
+impl Mbc5 {
+    pub fn new(has_rumble: bool) -> Self {
+        Mbc5 {
+            ram_enabled: false,
+            rom_bank: 0x1,
+            ram_bank: 0x0,
+            has_rumble: has_rumble,
+            rumble: false
+        }
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn write_control(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000 ... 0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000 ... 0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000 ... 0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 0b0000_0001u16) << 8);
+            },
+            0x4000 ... 0x5FFF => {
+                if self.has_rumble {
+                    // Rumble carts wire bit 3 to the motor instead of the RAM chip.
+                    self.rumble = value & 0b0000_1000u8 != 0;
+                    self.ram_bank = value & 0b0000_0111u8;
+                } else {
+                    self.ram_bank = value & 0b0000_1111u8;
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank as usize
+    }
+
+    fn is_ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    fn rumble(&self) -> Option<bool> {
+        if self.has_rumble {
+            Some(self.rumble)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use mem::*;
+    use cartridge::*;
+
+    // An 8 MiB MBC5 cartridge with 128 KiB of RAM, where the first 2 bytes of
+    // each ROM bank hold its bank number.
+    fn mem_with_mbc5(cartridge_type: u8) -> Mem {
+        let mut rom = vec![0x0; 0x800000];
+        for bank in 0 .. 0x200 {
+            rom[bank * 0x4000] = bank as u8;
+            rom[bank * 0x4000 + 1] = (bank >> 8) as u8;
+        }
+        rom[0x147] = cartridge_type;
+        rom[0x148] = 0x08;
+        rom[0x149] = 0x04;
+        let mut mem = Mem::new();
+        mem.insert_cartridge(new_cartridge(rom).ok().unwrap());
+        mem.write_u8(0x0000, 0x0A);
+        mem
+    }
+
+    fn rom_bank(mem: &Mem) -> usize {
+        mem.read_u8(0x4000) as usize | (mem.read_u8(0x4001) as usize) << 8
+    }
+
+    #[test]
+    fn rom_bank_has_9_bits() {
+        let mut mem = mem_with_mbc5(0x1B);
+        assert_eq!(rom_bank(&mem), 0x001);
+        mem.write_u8(0x2FFF, 0x23);
+        mem.write_u8(0x3000, 0x01);
+        assert_eq!(rom_bank(&mem), 0x123);
+        mem.write_u8(0x2000, 0xFF);
+        assert_eq!(rom_bank(&mem), 0x1FF);
+        // Only bit 0 of the high register is used.
+        mem.write_u8(0x3FFF, 0xFE);
+        assert_eq!(rom_bank(&mem), 0x0FF);
+    }
+
+    #[test]
+    fn rom_bank_0_can_be_selected() {
+        let mut mem = mem_with_mbc5(0x1B);
+        mem.write_u8(0x2000, 0x00);
+        assert_eq!(rom_bank(&mem), 0x000);
+        mem.write_u8(0x3000, 0x01);
+        assert_eq!(rom_bank(&mem), 0x100);
+    }
+
+    #[test]
+    fn rumble_bit_is_masked_out_of_the_ram_bank() {
+        let mut mem = mem_with_mbc5(0x1E);
+        let rumbling = Rc::new(Cell::new(false));
+        let callback_rumbling = rumbling.clone();
+        mem.set_rumble_callback(Box::new(move |on| callback_rumbling.set(on)));
+        mem.write_u8(0x4000, 0x02);
+        mem.write_u8(0xA000, 0x12);
+        mem.write_u8(0x4000, 0x0A);
+        assert!(rumbling.get());
+        assert_eq!(mem.read_u8(0xA000), 0x12);
+        mem.write_u8(0x4000, 0x02);
+        assert!(!rumbling.get());
+
+        // Without a motor, bit 3 is just part of the RAM bank.
+        let mut mem = mem_with_mbc5(0x1B);
+        mem.write_u8(0x4000, 0x02);
+        mem.write_u8(0xA000, 0x12);
+        mem.write_u8(0x4000, 0x0A);
+        assert_eq!(mem.read_u8(0xA000), 0x00);
+    }
+}
@@ -0,0 +1,102 @@
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+// A memory bank controller only tracks which banks are mapped in; the ROM and
+// RAM contents themselves belong to the cartridge.
+pub trait Mbc {
+    // Writes to 0x0000-0x7FFF never reach ROM, they set MBC registers instead.
+    fn write_control(&mut self, addr: u16, value: u8);
+
+    // Bank mapped into 0x0000-0x3FFF.
+    fn rom_bank0(&self) -> usize {
+        0
+    }
+
+    // Bank mapped into 0x4000-0x7FFF.
+    fn rom_bank(&self) -> usize;
+
+    fn ram_bank(&self) -> usize {
+        0
+    }
+
+    fn is_ram_enabled(&self) -> bool;
+
+    // Most carts have as much RAM as the header says, but some MBCs bring their own.
+    fn ram_size(&self, header_ram_size: usize) -> usize {
+        header_ram_size
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.is_ram_enabled() || ram.is_empty() {
+            return 0xFF;
+        }
+        ram[ram_offset(ram, self.ram_bank(), addr)]
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if !self.is_ram_enabled() || ram.is_empty() {
+            return;
+        }
+        let offset = ram_offset(ram, self.ram_bank(), addr);
+        ram[offset] = value;
+    }
+
+    // Advances anything on the cartridge that runs on its own clock.
+    fn tick(&mut self, _cycles: u32) {}
+
+    // Extra state persisted after the RAM contents in the save file.
+    fn save_extra(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_extra(&mut self, _data: &[u8]) {}
+
+    // Whether the rumble motor is on, for carts that have one.
+    fn rumble(&self) -> Option<bool> {
+        None
+    }
+}
+
+// Carts with less than a full bank of RAM (i.e. 2 KiB) mirror it.
+fn ram_offset(ram: &[u8], bank: usize, addr: u16) -> usize {
+    (bank * RAM_BANK_SIZE + (addr as usize - 0xA000)) % ram.len()
+}
+
+// For 32 KiB carts that have no MBC at all.
+pub struct NoMbc;
+
+impl Mbc for NoMbc {
+    fn write_control(&mut self, _addr: u16, _value: u8) {}
+
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    fn is_ram_enabled(&self) -> bool {
+        true
+    }
+}
+
+// Decodes the RAM size byte at 0x149.
+pub fn ram_size(code: u8) -> usize {
+    match code {
+        0x01 => 0x800,
+        0x02 => RAM_BANK_SIZE,
+        0x03 => RAM_BANK_SIZE * 4,
+        0x04 => RAM_BANK_SIZE * 16,
+        0x05 => RAM_BANK_SIZE * 8,
+        _ => 0
+    }
+}
+
+pub fn has_battery(cartridge_type: u8) -> bool {
+    match cartridge_type {
+        0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF => true,
+        _ => false
+    }
+}
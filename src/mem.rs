@@ -1,27 +1,101 @@
 use util::*;
-use std::io::{Write, Seek, SeekFrom};
-use std::fs::File;
+use interrupt::*;
+use cartridge::*;
+use joypad::*;
+use serial::*;
+use timer::*;
+use apu::*;
+use ppu::*;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Region {
+    CartridgeRom,
+    Vram,
+    CartridgeRam,
+    InternalRam,
+    EchoRam,
+    Oam,
+    Unusable,
+    Io,
+    HighRam,
+    InterruptEnable
+}
+
+// (start, end, region), with exclusive ends so 0x10000 can terminate the table.
+const MEMORY_MAP: [(u32, u32, Region); 10] = [
+    (0x0000, 0x8000, Region::CartridgeRom),
+    (0x8000, 0xA000, Region::Vram),
+    (0xA000, 0xC000, Region::CartridgeRam),
+    (0xC000, 0xE000, Region::InternalRam),
+    (0xE000, 0xFE00, Region::EchoRam),
+    (0xFE00, 0xFEA0, Region::Oam),
+    (0xFEA0, 0xFF00, Region::Unusable),
+    (0xFF00, 0xFF80, Region::Io),
+    (0xFF80, 0xFFFF, Region::HighRam),
+    (0xFFFF, 0x10000, Region::InterruptEnable)
+];
+
+// Finds the region containing addr, along with addr's offset into it.
+fn region(addr: u16) -> (Region, usize) {
+    let addr = addr as u32;
+    for &(start, end, region) in MEMORY_MAP.iter() {
+        if addr >= start && addr < end {
+            return (region, (addr - start) as usize);
+        }
+    }
+    unreachable!("The memory map covers the whole 16-bit address space.")
+}
 
 pub struct Mem {
-    fixed_rom_bank: Vec<u8>,
-    switchable_rom_bank: Vec<u8>,
+    // With no cartridge inserted, the cartridge half of the bus just reads 0xFF.
+    cartridge: Option<Box<Cartridge>>,
+    rumble_callback: Option<Box<FnMut(bool)>>,
     vram: Vec<u8>,
-    switchable_ram: Vec<u8>,
     internal_ram_8kb: Vec<u8>,
-    io_ports: Vec<u8>,
-    high_ram: Vec<u8>
+    oam: Vec<u8>,
+    high_ram: Vec<u8>,
+    // Each I/O register belongs to the subsystem it controls.
+    interrupts: Interrupts,
+    joypad: Joypad,
+    serial: Serial,
+    timer: Timer,
+    apu: Apu,
+    ppu: Ppu,
+    // DMA (0xFF46) reads back whatever was last written.
+    dma: u8,
+    // OAM DMA copies a byte per machine cycle, starting a machine cycle after
+    // the write. A new transfer can be pending while an old one finishes.
+    dma_pending: Option<u16>,
+    dma_source: u16,
+    dma_index: Option<usize>,
+    dma_cycles: u32,
+    // KEY1 (0xFF4D), the CGB speed switch.
+    speed_switch_armed: bool,
+    double_speed: bool
 }
 
 impl Default for Mem {
     fn default() -> Self {
         Mem {
-            fixed_rom_bank: Vec::with_capacity(0x4000),
-            switchable_rom_bank: Vec::with_capacity(0x4000),
-            vram: vec![0x0; 0x2000+1],
-            switchable_ram: vec![0x0; 0x2000+1],
-            internal_ram_8kb: vec![0x0; 0x2000+1],
-            io_ports: vec![0x0; 0x4C+1],
-            high_ram: vec![0x0; 0x7F+1]
+            cartridge: None,
+            rumble_callback: None,
+            vram: vec![0x0; 0x2000],
+            internal_ram_8kb: vec![0x0; 0x2000],
+            oam: vec![0x0; 0xA0],
+            high_ram: vec![0x0; 0x7F],
+            interrupts: Interrupts::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            timer: Timer::new(),
+            apu: Apu::new(),
+            ppu: Ppu::new(),
+            dma: 0x0,
+            dma_pending: None,
+            dma_source: 0x0,
+            dma_index: None,
+            dma_cycles: 0,
+            speed_switch_armed: false,
+            double_speed: false
         }
     }
 }
@@ -31,100 +105,514 @@ impl Mem {
         Mem::default()
     }
 
-    pub fn load_fixed_rom_bank(&mut self, contents: &[u8]) -> () {
-        self.fixed_rom_bank.extend_from_slice(contents)
-    }
-
-    fn memory_map(&self, addr: u16) -> (&[u8], usize) {
-        if addr <= 0x4000 {
-            (&self.fixed_rom_bank, addr as usize)
-        } else if addr <= 0x8000 {
-            (&self.switchable_rom_bank, (addr-0x4000) as usize)
-        } else if addr <= 0xA000 {
-            (&self.vram, (addr-0x8000) as usize)
-        } else if addr <= 0xC000 {
-            (&self.switchable_ram, (addr-0xA000) as usize)
-        } else if addr <= 0xE000 {
-            (&self.internal_ram_8kb, (addr-0xC000) as usize)
-        } else if addr <= 0xFE00 {
-            panic!("Memory address in echo, which is unimplemented. {}", addr);
-        } else if addr <= 0xFEA0 {
-            panic!("Memory address in OAM, which is unimplemented. {}", addr);
-        } else if addr <= 0xFF00 {
-            panic!("Memory address in empty and unusable #1, which is unimplemented. {}", addr);
-        } else if addr <= 0xFF4C {
-            //println!("IO Read {} ({})", hexdump(addr), addr-0xFF00);
-            (&self.io_ports, (addr-0xFF00) as usize)
-        } else if addr <= 0xFF80 {
-            panic!("Memory address in empty and unusable #2, which is unimplemented. {}", addr);
-        } else {
-            (&self.high_ram, (addr-0xFF80) as usize)
-        }
-    }
-
-    fn memory_map_mut(&mut self, addr: u16) -> (&mut [u8], usize) {
-        if addr <= 0x4000 {
-            (&mut self.fixed_rom_bank, addr as usize)
-        } else if addr <= 0x8000 {
-            (&mut self.switchable_rom_bank, (addr-0x4000) as usize)
-        } else if addr <= 0xA000 {
-            (&mut self.vram, (addr-0x8000) as usize)
-        } else if addr <= 0xC000 {
-            (&mut self.switchable_ram, (addr-0xA000) as usize)
-        } else if addr <= 0xE000 {
-            (&mut self.internal_ram_8kb, (addr-0xC000) as usize)
-        } else if addr <= 0xFE00 {
-            panic!("Memory address in echo, which is unimplemented. {}", addr);
-        } else if addr <= 0xFEA0 {
-            panic!("Memory address in OAM, which is unimplemented. {}", addr);
-        } else if addr <= 0xFF00 {
-            panic!("Memory address in empty and unusable #1, which is unimplemented. {}", addr);
-        } else if addr <= 0xFF4C {
-            //println!("IO Write {} ({})", hexdump(addr), addr-0xFF00);
-            (&mut self.io_ports, (addr-0xFF00) as usize)
-        } else if addr <= 0xFF80 {
-            panic!("Memory address in empty and unusable #2, which is unimplemented. {}", addr);
-        } else {
-            (&mut self.high_ram, (addr-0xFF80) as usize)
+    pub fn insert_cartridge(&mut self, cartridge: Box<Cartridge>) {
+        self.cartridge = Some(cartridge);
+    }
+
+    pub fn save_ram(&self) -> Vec<u8> {
+        self.cartridge.as_ref().map(|cartridge| cartridge.save_ram()).unwrap_or_default()
+    }
+
+    pub fn load_save_ram(&mut self, data: &[u8]) {
+        if let Some(ref mut cartridge) = self.cartridge {
+            cartridge.load_save_ram(data);
+        }
+    }
+
+    pub fn take_save_request(&mut self) -> bool {
+        self.cartridge.as_mut().map_or(false, |cartridge| cartridge.take_save_request())
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.dma_tick(cycles);
+        // The PPU, APU and cartridge RTC keep the same pace in double speed
+        // mode, so they see half the cycles.
+        let normal_speed_cycles = if self.double_speed { cycles / 2 } else { cycles };
+        if let Some(ref mut cartridge) = self.cartridge {
+            cartridge.tick(normal_speed_cycles);
+        }
+        self.timer.tick(cycles, &mut self.interrupts);
+        self.serial.tick(cycles, &mut self.interrupts);
+        self.ppu.tick(normal_speed_cycles, &self.vram, &self.oam, &mut self.interrupts);
+        self.apu.tick(normal_speed_cycles);
+    }
+
+    pub fn set_rumble_callback(&mut self, callback: Box<FnMut(bool)>) {
+        self.rumble_callback = Some(callback);
+    }
+
+    fn write_cartridge_rom(&mut self, addr: u16, value: u8) {
+        if let Some(ref mut cartridge) = self.cartridge {
+            let was_rumbling = cartridge.rumble();
+            cartridge.write_rom(addr, value);
+            let rumbling = cartridge.rumble();
+            if rumbling != was_rumbling {
+                if let (Some(on), Some(callback)) = (rumbling, self.rumble_callback.as_mut()) {
+                    callback(on);
+                }
+            }
+        }
+    }
+
+    // Only regions backed by plain memory have a slice to hand out.
+    fn memory_map(&self, region: Region) -> &[u8] {
+        match region {
+            Region::Vram => &self.vram,
+            // Echo RAM mirrors 0xC000-0xDDFF.
+            Region::InternalRam | Region::EchoRam => &self.internal_ram_8kb,
+            Region::Oam => &self.oam,
+            Region::HighRam => &self.high_ram,
+            _ => unreachable!("{:?} isn't backed by memory.", region)
+        }
+    }
+
+    fn memory_map_mut(&mut self, region: Region) -> &mut [u8] {
+        match region {
+            Region::Vram => &mut self.vram,
+            Region::InternalRam | Region::EchoRam => &mut self.internal_ram_8kb,
+            Region::Oam => &mut self.oam,
+            Region::HighRam => &mut self.high_ram,
+            _ => unreachable!("{:?} isn't backed by memory.", region)
+        }
+    }
+
+    fn dma_tick(&mut self, cycles: u32) {
+        let cycles = self.dma_cycles + cycles;
+        for _ in 0 .. cycles / 4 {
+            if let Some(index) = self.dma_index {
+                let value = self.bus_read(self.dma_source + index as u16);
+                self.oam[index] = value;
+                self.dma_index = if index + 1 < self.oam.len() { Some(index + 1) } else { None };
+            }
+            if let Some(source) = self.dma_pending.take() {
+                self.dma_source = source;
+                self.dma_index = Some(0);
+            }
         }
+        self.dma_cycles = cycles % 4;
+    }
+
+    fn start_dma(&mut self, value: u8) {
+        self.dma = value;
+        // Sources past 0xDFFF see echo RAM, even for 0xFE and 0xFF.
+        let page = if value >= 0xE0 { value - 0x20 } else { value };
+        self.dma_pending = Some((page as u16) << 8);
+    }
+
+    pub fn is_dma_active(&self) -> bool {
+        self.dma_index.is_some()
+    }
+
+    // While DMA owns the main bus, the CPU is left with HRAM and the I/O
+    // registers, which is enough to wait out or restart the transfer.
+    fn is_cpu_accessible(&self, addr: u16) -> bool {
+        !self.is_dma_active() || addr >= 0xFF00
     }
 
     pub fn read_u8(&self, addr: u16) -> u8 {
-        let pair = self.memory_map(addr);
-        pair.0[pair.1]
+        if !self.is_cpu_accessible(addr) {
+            return 0xFF;
+        }
+        self.bus_read(addr)
+    }
+
+    fn bus_read(&self, addr: u16) -> u8 {
+        match region(addr) {
+            (Region::CartridgeRom, _) => {
+                self.cartridge.as_ref().map_or(0xFF, |cartridge| cartridge.read_rom(addr))
+            },
+            (Region::CartridgeRam, _) => {
+                self.cartridge.as_ref().map_or(0xFF, |cartridge| cartridge.read_ram(addr))
+            },
+            (Region::Unusable, _) => 0x00,
+            (Region::InterruptEnable, _) => self.interrupts.read_enable(),
+            (Region::Io, _) => self.io_read(addr),
+            (region, offset) => self.memory_map(region)[offset]
+        }
+    }
+
+    fn io_read(&self, addr: u16) -> u8 {
+        match addr {
+            //println!("IO Read {} ({})", hexdump(addr), addr-0xFF00);
+            0xFF00 => self.joypad.read(),
+            0xFF01 ... 0xFF02 => self.serial.read(addr),
+            0xFF04 ... 0xFF07 => self.timer.read(addr),
+            0xFF0F => self.interrupts.read_flag(),
+            0xFF10 ... 0xFF3F => self.apu.read(addr),
+            0xFF46 => self.dma,
+            0xFF40 ... 0xFF4B => self.ppu.read(addr),
+            0xFF4D => 0b0111_1110u8 | (self.double_speed as u8) << 7 | self.speed_switch_armed as u8,
+            // Addresses with no register behind them read back as 0xFF.
+            _ => 0xFF
+        }
     }
 
+    // 16-bit accesses are really two 8-bit bus accesses, so they can straddle regions.
     pub fn read_u16(&self, addr: u16) -> u16 {
-        let pair = self.memory_map(addr);
-        get_u16(pair.0, pair.1)
+        u16_from_2u8s((self.read_u8(addr), self.read_u8(addr.wrapping_add(1))))
     }
 
     pub fn write_u8(&mut self, addr: u16, value: u8) {
-        let pair = self.memory_map_mut(addr);
-        pair.0[pair.1] = value;
+        if !self.is_cpu_accessible(addr) {
+            return;
+        }
+        match region(addr) {
+            (Region::CartridgeRom, _) => self.write_cartridge_rom(addr, value),
+            (Region::CartridgeRam, _) => {
+                if let Some(ref mut cartridge) = self.cartridge {
+                    cartridge.write_ram(addr, value);
+                }
+            },
+            (Region::Unusable, _) => (),
+            (Region::InterruptEnable, _) => self.interrupts.write_enable(value),
+            (Region::Io, _) => self.io_write(addr, value),
+            (region, offset) => self.memory_map_mut(region)[offset] = value
+        }
+    }
+
+    fn io_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            //println!("IO Write {} ({})", hexdump(addr), addr-0xFF00);
+            0xFF00 => self.joypad.write(value, &mut self.interrupts),
+            0xFF01 ... 0xFF02 => self.serial.write(addr, value),
+            0xFF04 ... 0xFF07 => self.timer.write(addr, value),
+            0xFF0F => self.interrupts.write_flag(value),
+            0xFF10 ... 0xFF3F => self.apu.write(addr, value),
+            0xFF46 => self.start_dma(value),
+            0xFF40 ... 0xFF4B => self.ppu.write(addr, value),
+            0xFF4D => self.speed_switch_armed = value & 0b0000_0001u8 != 0,
+            _ => ()
+        }
+    }
+
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupts.acknowledge(interrupt);
+    }
+
+    pub fn is_interrupt_requested(&self, interrupt: Interrupt) -> bool {
+        self.interrupts.is_requested(interrupt)
+    }
+
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        self.interrupts.pending()
     }
 
     pub fn write_u16(&mut self, addr: u16, value: u16) {
-        let pair = self.memory_map_mut(addr);
         let values = u16_to_2u8s(value);
-        pair.0[pair.1] = values.0;
-        pair.0[pair.1+1] = values.1;
-    }
-
-    pub fn dump(&self) {
-        let mut f = File::create("memdump.bin").unwrap();
-        f.write_all(&self.fixed_rom_bank).unwrap();
-        f.seek(SeekFrom::Start(0x4000)).unwrap();
-        f.write_all(&self.switchable_rom_bank).unwrap();
-        f.seek(SeekFrom::Start(0x8000)).unwrap();
-        f.write_all(&self.vram).unwrap();
-        f.seek(SeekFrom::Start(0xA000)).unwrap();
-        f.write_all(&self.switchable_ram).unwrap();
-        f.seek(SeekFrom::Start(0xE000)).unwrap();
-        f.write_all(&self.internal_ram_8kb).unwrap();
-        f.seek(SeekFrom::Start(0xFF4C)).unwrap();
-        f.write_all(&self.io_ports).unwrap();
-        f.seek(SeekFrom::Start(0xFF80)).unwrap();
-        f.write_all(&self.high_ram).unwrap();
+        // Little-endian, so the low byte goes first.
+        self.write_u8(addr, values.1);
+        self.write_u8(addr.wrapping_add(1), values.0);
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.ppu.set_renderer(renderer);
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.joypad.set_button(button, pressed, &mut self.interrupts);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.set_sample_rate(sample_rate);
+    }
+
+    pub fn pull_samples(&mut self, buffer: &mut [f32]) -> usize {
+        self.apu.pull_samples(buffer)
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        self.ppu.framebuffer()
+    }
+
+    pub fn take_frame_ready(&mut self) -> bool {
+        self.ppu.take_frame_ready()
+    }
+
+    pub fn reset_div(&mut self) {
+        self.timer.reset_div();
+    }
+
+    pub fn is_speed_switch_armed(&self) -> bool {
+        self.speed_switch_armed
+    }
+
+    pub fn switch_speed(&mut self) {
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mem_with_rom() -> Mem {
+        // A plain 32 KiB ROM with 1 bank of RAM, tagged at both ends of each ROM bank.
+        let mut rom = vec![0x0; 0x8000];
+        rom[0x0000] = 0x11;
+        rom[0x3FFF] = 0x22;
+        rom[0x4000] = 0x33;
+        rom[0x7FFF] = 0x44;
+        rom[0x149] = 0x02;
+        let mut mem = Mem::new();
+        mem.insert_cartridge(new_cartridge(rom).ok().unwrap());
+        mem
+    }
+
+    #[test]
+    fn memory_map_is_contiguous() {
+        assert_eq!(MEMORY_MAP[0].0, 0x0000);
+        for pair in MEMORY_MAP.windows(2) {
+            assert_eq!(pair[0].1, pair[1].0);
+        }
+        assert_eq!(MEMORY_MAP[MEMORY_MAP.len()-1].1, 0x10000);
+    }
+
+    #[test]
+    fn region_boundaries() {
+        for &(start, end, expected) in MEMORY_MAP.iter() {
+            assert_eq!(region(start as u16), (expected, 0));
+            assert_eq!(region((end - 1) as u16), (expected, (end - 1 - start) as usize));
+        }
+    }
+
+    #[test]
+    fn buffers_match_region_sizes() {
+        let mem = Mem::new();
+        for &(start, end, region) in MEMORY_MAP.iter() {
+            match region {
+                Region::Vram | Region::InternalRam | Region::Oam | Region::HighRam => {
+                    assert_eq!(mem.memory_map(region).len(), (end - start) as usize);
+                },
+                _ => ()
+            }
+        }
+    }
+
+    #[test]
+    fn cartridge_rom_boundaries() {
+        let mut mem = mem_with_rom();
+        assert_eq!(mem.read_u8(0x0000), 0x11);
+        assert_eq!(mem.read_u8(0x3FFF), 0x22);
+        assert_eq!(mem.read_u8(0x4000), 0x33);
+        assert_eq!(mem.read_u8(0x7FFF), 0x44);
+        mem.write_u8(0x8000, 0x55);
+        assert_eq!(mem.read_u8(0x7FFF), 0x44);
+        assert_eq!(mem.read_u8(0x8000), 0x55);
+    }
+
+    #[test]
+    fn no_cartridge_reads_open_bus() {
+        let mem = Mem::new();
+        assert_eq!(mem.read_u8(0x0000), 0xFF);
+        assert_eq!(mem.read_u8(0x7FFF), 0xFF);
+        assert_eq!(mem.read_u8(0xA000), 0xFF);
+        assert_eq!(mem.read_u8(0xBFFF), 0xFF);
+    }
+
+    #[test]
+    fn vram_boundaries() {
+        let mut mem = mem_with_rom();
+        mem.write_u8(0x9FFF, 0x12);
+        assert_eq!(mem.read_u8(0x9FFF), 0x12);
+        mem.write_u8(0xA000, 0x34);
+        assert_eq!(mem.read_u8(0xA000), 0x34);
+        assert_eq!(mem.read_u8(0x9FFF), 0x12);
+    }
+
+    #[test]
+    fn cartridge_ram_boundaries() {
+        let mut mem = Mem::new();
+        let mut rom = vec![0x0; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        mem.insert_cartridge(new_cartridge(rom).ok().unwrap());
+        mem.write_u8(0x0000, 0x0A);
+        mem.write_u8(0xA000, 0x12);
+        mem.write_u8(0xBFFF, 0x34);
+        mem.write_u8(0xC000, 0x56);
+        assert_eq!(mem.read_u8(0xA000), 0x12);
+        assert_eq!(mem.read_u8(0xBFFF), 0x34);
+        assert_eq!(mem.read_u8(0xC000), 0x56);
+    }
+
+    #[test]
+    fn echo_ram_boundaries() {
+        let mut mem = Mem::new();
+        mem.write_u8(0xC000, 0x12);
+        mem.write_u8(0xDDFF, 0x34);
+        mem.write_u8(0xDFFF, 0x56);
+        assert_eq!(mem.read_u8(0xE000), 0x12);
+        assert_eq!(mem.read_u8(0xFDFF), 0x34);
+        // 0xDE00-0xDFFF has no echo; 0xFE00 onwards is OAM.
+        assert_eq!(mem.read_u8(0xFE00), 0x00);
+        mem.write_u8(0xFDFF, 0x78);
+        assert_eq!(mem.read_u8(0xDDFF), 0x78);
+        assert_eq!(mem.read_u8(0xDFFF), 0x56);
+    }
+
+    #[test]
+    fn oam_and_unusable_boundaries() {
+        let mut mem = Mem::new();
+        mem.write_u8(0xFE00, 0x12);
+        mem.write_u8(0xFE9F, 0x34);
+        mem.write_u8(0xFEA0, 0x56);
+        mem.write_u8(0xFEFF, 0x78);
+        assert_eq!(mem.read_u8(0xFE00), 0x12);
+        assert_eq!(mem.read_u8(0xFE9F), 0x34);
+        assert_eq!(mem.read_u8(0xFEA0), 0x00);
+        assert_eq!(mem.read_u8(0xFEFF), 0x00);
+    }
+
+    #[test]
+    fn io_and_high_ram_boundaries() {
+        let mut mem = Mem::new();
+        mem.write_u8(0xFF7F, 0x12);
+        mem.write_u8(0xFF80, 0x34);
+        mem.write_u8(0xFFFE, 0x56);
+        mem.write_u8(0xFFFF, 0x78);
+        // 0xFF7F has no register behind it.
+        assert_eq!(mem.read_u8(0xFF7F), 0xFF);
+        assert_eq!(mem.read_u8(0xFF80), 0x34);
+        assert_eq!(mem.read_u8(0xFFFE), 0x56);
+        assert_eq!(mem.read_u8(0xFFFF), 0x78);
+    }
+
+    #[test]
+    fn io_registers_apply_read_masks() {
+        let mut mem = Mem::new();
+        mem.write_u8(0xFF00, 0x00);
+        assert_eq!(mem.read_u8(0xFF00), 0xCF);
+        mem.write_u8(0xFF07, 0xFF);
+        assert_eq!(mem.read_u8(0xFF07), 0xFF);
+        mem.write_u8(0xFF07, 0x00);
+        assert_eq!(mem.read_u8(0xFF07), 0xF8);
+        mem.write_u8(0xFF0F, 0x00);
+        assert_eq!(mem.read_u8(0xFF0F), 0xE0);
+        // STAT's mode bits are read-only (the LCD starts out in mode 2), and LY
+        // can't be written at all.
+        mem.write_u8(0xFF41, 0xFF);
+        assert_eq!(mem.read_u8(0xFF41), 0xFE);
+        mem.write_u8(0xFF44, 0x12);
+        assert_eq!(mem.read_u8(0xFF44), 0x00);
+    }
+
+    #[test]
+    fn ly_advances_from_reset() {
+        // The boot ROM leaves the LCD on, so games can wait on LY straight away.
+        let mut mem = Mem::new();
+        assert_eq!(mem.read_u8(0xFF40), 0x91);
+        assert_eq!(mem.read_u8(0xFF47), 0xFC);
+        assert_eq!(mem.read_u8(0xFF44), 0x00);
+        mem.tick(456);
+        assert_eq!(mem.read_u8(0xFF44), 0x01);
+        mem.tick(143 * 456);
+        assert_eq!(mem.read_u8(0xFF44), 0x90);
+        assert_eq!(mem.read_u8(0xFF41) & 0b11, 1);
+        assert!(mem.is_interrupt_requested(Interrupt::VBlank));
+    }
+
+    #[test]
+    fn apu_registers_ignore_writes_while_powered_off() {
+        let mut mem = Mem::new();
+        mem.write_u8(0xFF12, 0xF3);
+        assert_eq!(mem.read_u8(0xFF12), 0x00);
+        mem.write_u8(0xFF26, 0x80);
+        mem.write_u8(0xFF12, 0xF3);
+        assert_eq!(mem.read_u8(0xFF12), 0xF3);
+        // NR11's length bits are write-only.
+        mem.write_u8(0xFF11, 0xFF);
+        assert_eq!(mem.read_u8(0xFF11), 0xFF);
+        mem.write_u8(0xFF11, 0x00);
+        assert_eq!(mem.read_u8(0xFF11), 0x3F);
+        mem.write_u8(0xFF26, 0x00);
+        assert_eq!(mem.read_u8(0xFF12), 0x00);
+        assert_eq!(mem.read_u8(0xFF26), 0x70);
+    }
+
+    #[test]
+    fn joypad_reads_selected_buttons_and_interrupts_on_press() {
+        let mut mem = Mem::new();
+        mem.write_u8(0xFF00, 0x20);
+        mem.set_button(Button::Down, true);
+        mem.set_button(Button::A, true);
+        assert_eq!(mem.read_u8(0xFF00), 0xE7);
+        assert!(mem.is_interrupt_requested(Interrupt::Joypad));
+        mem.acknowledge_interrupt(Interrupt::Joypad);
+        // A is held, so selecting the buttons pulls P10 low.
+        mem.write_u8(0xFF00, 0x10);
+        assert_eq!(mem.read_u8(0xFF00), 0xDE);
+        assert!(mem.is_interrupt_requested(Interrupt::Joypad));
+        mem.acknowledge_interrupt(Interrupt::Joypad);
+        // Releasing a button never interrupts.
+        mem.set_button(Button::A, false);
+        assert_eq!(mem.read_u8(0xFF00), 0xDF);
+        assert!(!mem.is_interrupt_requested(Interrupt::Joypad));
+    }
+
+    #[test]
+    fn oam_dma_copies_after_160_machine_cycles() {
+        let mut mem = Mem::new();
+        for i in 0 .. 0xA0 {
+            mem.write_u8(0xC100 + i, i as u8);
+        }
+        mem.write_u8(0xFF46, 0xC1);
+        assert_eq!(mem.read_u8(0xFF46), 0xC1);
+        // One machine cycle of setup, then one byte per machine cycle.
+        mem.tick(4);
+        assert!(mem.is_dma_active());
+        mem.tick(159 * 4);
+        assert!(mem.is_dma_active());
+        mem.tick(4);
+        assert!(!mem.is_dma_active());
+        assert_eq!(mem.read_u8(0xFE00), 0x00);
+        assert_eq!(mem.read_u8(0xFE9F), 0x9F);
+    }
+
+    #[test]
+    fn oam_dma_restricts_cpu_to_high_ram() {
+        let mut mem = Mem::new();
+        mem.write_u8(0xC000, 0x12);
+        mem.write_u8(0xFF80, 0x34);
+        mem.write_u8(0xFF46, 0xC0);
+        mem.tick(8);
+        assert_eq!(mem.read_u8(0xC000), 0xFF);
+        mem.write_u8(0xC000, 0x56);
+        assert_eq!(mem.read_u8(0xFF80), 0x34);
+        mem.write_u8(0xFF80, 0x78);
+        assert_eq!(mem.read_u8(0xFF80), 0x78);
+        // Restarting mid-transfer copies all 160 bytes from the new source.
+        mem.write_u8(0xFF46, 0xC0);
+        mem.tick(100 * 4);
+        assert!(mem.is_dma_active());
+        mem.tick(61 * 4);
+        assert!(!mem.is_dma_active());
+        assert_eq!(mem.read_u8(0xC000), 0x12);
+        assert_eq!(mem.read_u8(0xFE00), 0x12);
+    }
+
+    #[test]
+    fn u16_access_is_little_endian() {
+        let mut mem = Mem::new();
+        mem.write_u16(0xC000, 0x1234);
+        assert_eq!(mem.read_u8(0xC000), 0x34);
+        assert_eq!(mem.read_u8(0xC001), 0x12);
+        assert_eq!(mem.read_u16(0xC000), 0x1234);
+    }
+
+    #[test]
+    fn u16_access_straddles_regions() {
+        let mut mem = Mem::new();
+        // The high byte lands at 0xE000, which mirrors 0xC000.
+        mem.write_u16(0xDFFF, 0x1234);
+        assert_eq!(mem.read_u8(0xDFFF), 0x34);
+        assert_eq!(mem.read_u8(0xC000), 0x12);
+        // The high byte lands in IE.
+        mem.write_u16(0xFFFE, 0xABCD);
+        assert_eq!(mem.read_u8(0xFFFE), 0xCD);
+        assert_eq!(mem.read_u8(0xFFFF), 0xAB);
+        assert_eq!(mem.read_u16(0xFFFE), 0xABCD);
+        // And wrapping around the top of the address space reaches the cartridge.
+        assert_eq!(mem.read_u16(0xFFFF), 0xFFAB);
     }
 }
@@ -0,0 +1,369 @@
+use std::collections::VecDeque;
+use super::*;
+
+const OAM_SCAN_DOTS: u32 = 80;
+// Each fetcher step before the push takes 2 dots.
+const FETCH_STEP_DOTS: u32 = 2;
+// Fetching a sprite stalls pixel output for at least this long.
+const SPRITE_FETCH_DOTS: u32 = 6;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push
+}
+
+#[derive(Copy, Clone, Default)]
+struct SpritePixel {
+    color: u8,
+    attributes: u8
+}
+
+// Per-line state of the pixel FIFO renderer.
+pub struct Fifo {
+    // Colour numbers waiting to be shifted out, before palettes are applied.
+    bg: VecDeque<u8>,
+    sprites: VecDeque<SpritePixel>,
+    step: FetchStep,
+    step_dots: u32,
+    // Which tile across the BG map or window the fetcher is on.
+    tile_x: u8,
+    tile_offset: usize,
+    tile_row: usize,
+    data_low: u8,
+    data_high: u8,
+    // The first tile of each line gets fetched twice, and the first copy is thrown away.
+    first_fetch: bool,
+    in_window: bool,
+    // Pixels still to be thrown away, which is how SCX scrolls by less than a tile.
+    discard: u8,
+    // The next screen column to draw.
+    x: u8,
+    line_sprites: Vec<[u8; 4]>,
+    // A sprite that's been reached, and how far along its fetch is.
+    pending_sprite: Option<[u8; 4]>,
+    sprite_fetch_dots: u32
+}
+
+impl Default for Fifo {
+    fn default() -> Self {
+        Fifo {
+            bg: VecDeque::with_capacity(8),
+            sprites: VecDeque::with_capacity(8),
+            step: FetchStep::Tile,
+            step_dots: 0,
+            tile_x: 0,
+            tile_offset: 0,
+            tile_row: 0,
+            data_low: 0x0,
+            data_high: 0x0,
+            first_fetch: true,
+            in_window: false,
+            discard: 0,
+            x: 0,
+            line_sprites: Vec::new(),
+            pending_sprite: None,
+            sprite_fetch_dots: 0
+        }
+    }
+}
+
+impl Fifo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Ppu {
+    pub fn fifo_tick(&mut self, cycles: u32, vram: &[u8], oam: &[u8], interrupts: &mut Interrupts) {
+        for _ in 0 .. cycles {
+            self.fifo_dot(vram, oam, interrupts);
+            self.update_stat_line(interrupts);
+        }
+    }
+
+    // Unlike the scanline renderer, dots count from the start of the line here.
+    fn fifo_dot(&mut self, vram: &[u8], oam: &[u8], interrupts: &mut Interrupts) {
+        self.dots += 1;
+        match self.mode {
+            Mode::OamScan => {
+                if self.dots == OAM_SCAN_DOTS {
+                    self.start_drawing(oam);
+                }
+            },
+            Mode::Drawing => {
+                self.draw_dot(vram);
+                // Mode 3 lasts however long it takes to get 160 pixels out.
+                if self.fifo.x as usize == SCREEN_WIDTH {
+                    if self.fifo.in_window {
+                        self.window_line += 1;
+                    }
+                    self.mode = Mode::HBlank;
+                }
+            },
+            Mode::HBlank | Mode::VBlank => {
+                if self.dots == LINE_DOTS {
+                    self.dots = 0;
+                    self.next_line(interrupts);
+                }
+            }
+        }
+    }
+
+    fn start_drawing(&mut self, oam: &[u8]) {
+        if self.ly == self.wy {
+            self.window_triggered = true;
+        }
+        self.fifo = Fifo::new();
+        self.fifo.line_sprites = self.oam_scan(oam);
+        self.fifo.discard = self.scx % 8;
+        self.mode = Mode::Drawing;
+    }
+
+    fn draw_dot(&mut self, vram: &[u8]) {
+        if self.fifo.pending_sprite.is_none() && self.lcdc & OBJ_ENABLE != 0 && self.fifo.discard == 0 {
+            // Sprites are fetched in OAM order once their left edge is reached.
+            let x = self.fifo.x as usize;
+            let reached = self.fifo.line_sprites.iter().position(|sprite| sprite[1] as usize <= x + 8);
+            if let Some(index) = reached {
+                self.fifo.pending_sprite = Some(self.fifo.line_sprites.remove(index));
+            }
+        }
+        if let Some(sprite) = self.fifo.pending_sprite {
+            // The sprite fetch has to wait for the BG fetcher to finish its tile.
+            if self.fifo.step != FetchStep::Push {
+                self.fetch_dot(vram);
+            } else {
+                self.fifo.sprite_fetch_dots += 1;
+                if self.fifo.sprite_fetch_dots == SPRITE_FETCH_DOTS {
+                    self.merge_sprite(vram, &sprite);
+                    self.fifo.pending_sprite = None;
+                    self.fifo.sprite_fetch_dots = 0;
+                }
+            }
+            return;
+        }
+        if !self.fifo.in_window
+            && self.lcdc & WINDOW_ENABLE != 0
+            && self.window_triggered
+            && self.fifo.x as usize + 7 >= self.wx as usize
+        {
+            // Hitting WX throws away the BG pixels and restarts the fetcher on the window.
+            self.fifo.in_window = true;
+            self.fifo.bg.clear();
+            self.fifo.step = FetchStep::Tile;
+            self.fifo.step_dots = 0;
+            self.fifo.tile_x = 0;
+            self.fifo.first_fetch = false;
+            // A WX below 7 pushes the window's left edge off screen.
+            self.fifo.discard = if self.fifo.x == 0 && self.wx < 7 { 7 - self.wx } else { 0 };
+        }
+        self.fetch_dot(vram);
+        self.shift_pixel();
+    }
+
+    fn fetch_dot(&mut self, vram: &[u8]) {
+        if self.fifo.step == FetchStep::Push {
+            // Pushing only succeeds once the FIFO has emptied.
+            if self.fifo.bg.is_empty() {
+                self.push_tile();
+            }
+            return;
+        }
+        self.fifo.step_dots += 1;
+        if self.fifo.step_dots < FETCH_STEP_DOTS {
+            return;
+        }
+        self.fifo.step_dots = 0;
+        match self.fifo.step {
+            FetchStep::Tile => {
+                // Scroll registers are sampled per tile, so mid-line writes take effect.
+                let (map_select, x, y) = if self.fifo.in_window {
+                    (WINDOW_TILE_MAP, self.fifo.tile_x as usize * 8, self.window_line as usize)
+                } else {
+                    let x = (self.scx as usize + self.fifo.tile_x as usize * 8) % 256;
+                    let y = (self.ly as usize + self.scy as usize) % 256;
+                    (BG_TILE_MAP, x, y)
+                };
+                self.fifo.tile_offset = self.tile_map_offset(vram, map_select, x, y);
+                self.fifo.tile_row = y % 8;
+                self.fifo.step = FetchStep::DataLow;
+            },
+            FetchStep::DataLow => {
+                self.fifo.data_low = vram[self.fifo.tile_offset + self.fifo.tile_row * 2];
+                self.fifo.step = FetchStep::DataHigh;
+            },
+            FetchStep::DataHigh => {
+                self.fifo.data_high = vram[self.fifo.tile_offset + self.fifo.tile_row * 2 + 1];
+                // The first fetch is thrown away without waiting to be pushed.
+                if self.fifo.first_fetch {
+                    self.fifo.first_fetch = false;
+                    self.fifo.step = FetchStep::Tile;
+                } else {
+                    self.fifo.step = FetchStep::Push;
+                }
+            },
+            FetchStep::Push => unreachable!("Pushing is handled before stepping the fetcher.")
+        }
+    }
+
+    fn push_tile(&mut self) {
+        self.fifo.step = FetchStep::Tile;
+        for bit in (0 .. 8).rev() {
+            let color = ((self.fifo.data_high >> bit) & 1) << 1 | (self.fifo.data_low >> bit) & 1;
+            self.fifo.bg.push_back(color);
+        }
+        self.fifo.tile_x = self.fifo.tile_x.wrapping_add(1);
+    }
+
+    fn merge_sprite(&mut self, vram: &[u8], sprite: &[u8; 4]) {
+        while self.fifo.sprites.len() < 8 {
+            self.fifo.sprites.push_back(SpritePixel::default());
+        }
+        // Sprites hanging off the left edge lose the columns that are off screen.
+        let hidden = self.fifo.x as usize + 8 - sprite[1] as usize;
+        for column in hidden .. 8 {
+            let color = self.sprite_color(vram, sprite, column);
+            let pixel = &mut self.fifo.sprites[column - hidden];
+            // Sprites fetched earlier have priority, so only transparent pixels get replaced.
+            if pixel.color == 0 && color != 0 {
+                *pixel = SpritePixel {
+                    color: color,
+                    attributes: sprite[3]
+                };
+            }
+        }
+    }
+
+    fn shift_pixel(&mut self) {
+        let bg_color = match self.fifo.bg.pop_front() {
+            Some(color) => color,
+            None => return
+        };
+        let sprite = self.fifo.sprites.pop_front().unwrap_or_default();
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        // LCDC and the palettes are sampled as each pixel comes out.
+        let bg_color = if self.lcdc & BG_ENABLE != 0 { bg_color } else { 0 };
+        let sprite_visible = sprite.color != 0
+            && self.lcdc & OBJ_ENABLE != 0
+            && !(sprite.attributes & OBJ_BEHIND_BG != 0 && bg_color != 0);
+        let shade = if sprite_visible {
+            let palette = if sprite.attributes & OBJ_PALETTE != 0 { self.obp1 } else { self.obp0 };
+            Self::shade(palette, sprite.color)
+        } else {
+            Self::shade(self.bgp, bg_color)
+        };
+        self.framebuffer[self.ly as usize * SCREEN_WIDTH + self.fifo.x as usize] = shade;
+        self.fifo.x += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Lcd {
+        ppu: Ppu,
+        vram: Vec<u8>,
+        oam: Vec<u8>,
+        interrupts: Interrupts
+    }
+
+    impl Lcd {
+        // Tile 0 is solid colour 1 and tile 1 is solid colour 0, with the BG map
+        // alternating between them every 8 pixels.
+        fn new(renderer: Renderer) -> Self {
+            let mut vram = vec![0x0; 0x2000];
+            for row in 0 .. 8 {
+                vram[row * 2] = 0xFF;
+            }
+            for column in 0 .. 32 {
+                vram[0x1800 + column] = (column % 2) as u8;
+            }
+            let mut ppu = Ppu::new();
+            ppu.set_renderer(renderer);
+            Lcd {
+                ppu: ppu,
+                vram: vram,
+                oam: vec![0x0; 0xA0],
+                interrupts: Interrupts::new()
+            }
+        }
+
+        fn tick(&mut self, dots: u32) {
+            self.ppu.tick(dots, &self.vram, &self.oam, &mut self.interrupts);
+        }
+
+        fn mode(&self) -> u8 {
+            self.ppu.read(0xFF41) & 0b11
+        }
+
+        // How long mode 3 lasts on the first line.
+        fn drawing_dots(&mut self) -> u32 {
+            self.tick(OAM_SCAN_DOTS);
+            let mut dots = 0;
+            while self.mode() == 3 {
+                self.tick(1);
+                dots += 1;
+            }
+            dots
+        }
+    }
+
+    #[test]
+    fn mode_3_lengthens_with_fine_scroll_and_sprites() {
+        let mut lcd = Lcd::new(Renderer::PixelFifo);
+        assert_eq!(lcd.drawing_dots(), 172);
+
+        let mut lcd = Lcd::new(Renderer::PixelFifo);
+        lcd.ppu.write(0xFF43, 3);
+        assert_eq!(lcd.drawing_dots(), 175);
+
+        let mut lcd = Lcd::new(Renderer::PixelFifo);
+        lcd.ppu.write(0xFF40, 0x93);
+        lcd.oam[0] = 16;
+        lcd.oam[1] = 8;
+        assert!(lcd.drawing_dots() >= 172 + SPRITE_FETCH_DOTS);
+    }
+
+    #[test]
+    fn mid_line_palette_writes_split_the_line() {
+        let mut lcd = Lcd::new(Renderer::PixelFifo);
+        for column in 0 .. 32 {
+            lcd.vram[0x1800 + column] = 0;
+        }
+        lcd.tick(OAM_SCAN_DOTS + 90);
+        lcd.ppu.write(0xFF47, 0xE4);
+        lcd.tick(LINE_DOTS);
+        // Colour 1 comes out as shade 3 under the boot palette, then shade 1.
+        assert_eq!(lcd.ppu.framebuffer()[0], 3);
+        assert_eq!(lcd.ppu.framebuffer()[SCREEN_WIDTH - 1], 1);
+
+        // The scanline renderer draws the whole line with whatever's set at the end.
+        let mut lcd = Lcd::new(Renderer::Scanline);
+        for column in 0 .. 32 {
+            lcd.vram[0x1800 + column] = 0;
+        }
+        lcd.tick(OAM_SCAN_DOTS + 90);
+        lcd.ppu.write(0xFF47, 0xE4);
+        lcd.tick(LINE_DOTS);
+        assert_eq!(lcd.ppu.framebuffer()[0], 1);
+    }
+
+    #[test]
+    fn mid_line_scroll_writes_apply_to_the_next_tile() {
+        let mut lcd = Lcd::new(Renderer::PixelFifo);
+        lcd.tick(OAM_SCAN_DOTS + 90);
+        lcd.ppu.write(0xFF43, 8);
+        lcd.tick(LINE_DOTS);
+        let line = &lcd.ppu.framebuffer()[0 .. SCREEN_WIDTH];
+        assert_eq!(&line[0 .. 16], &[3, 3, 3, 3, 3, 3, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // Column 19 would be tile 1, but scrolling a tile over fetches tile 0 instead.
+        assert_eq!(&line[152 .. 160], &[3; 8]);
+    }
+}
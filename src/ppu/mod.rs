@@ -0,0 +1,398 @@
+use interrupt::*;
+use self::fifo::*;
+
+mod fifo;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+// LCDC bits.
+const BG_ENABLE: u8 = 0b0000_0001u8;
+const OBJ_ENABLE: u8 = 0b0000_0010u8;
+const OBJ_SIZE: u8 = 0b0000_0100u8;
+const BG_TILE_MAP: u8 = 0b0000_1000u8;
+const TILE_DATA: u8 = 0b0001_0000u8;
+const WINDOW_ENABLE: u8 = 0b0010_0000u8;
+const WINDOW_TILE_MAP: u8 = 0b0100_0000u8;
+const LCD_ENABLE: u8 = 0b1000_0000u8;
+
+// STAT interrupt select bits.
+const HBLANK_INTERRUPT: u8 = 0b0000_1000u8;
+const VBLANK_INTERRUPT: u8 = 0b0001_0000u8;
+const OAM_SCAN_INTERRUPT: u8 = 0b0010_0000u8;
+const COINCIDENCE_INTERRUPT: u8 = 0b0100_0000u8;
+
+// Sprite attribute bits.
+const OBJ_PALETTE: u8 = 0b0001_0000u8;
+const OBJ_FLIP_X: u8 = 0b0010_0000u8;
+const OBJ_FLIP_Y: u8 = 0b0100_0000u8;
+const OBJ_BEHIND_BG: u8 = 0b1000_0000u8;
+
+const LINE_DOTS: u32 = 456;
+const VBLANK_LINES: u8 = 10;
+const SPRITES_PER_LINE: usize = 10;
+
+// How pixels get drawn. The scanline renderer draws each line in one go at
+// the end of mode 3, while the pixel FIFO renderer draws a dot at a time so
+// that mid-line register writes and mode 3's variable length come out right.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Renderer {
+    Scanline,
+    PixelFifo
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Mode {
+    HBlank,
+    VBlank,
+    OamScan,
+    Drawing
+}
+
+impl Mode {
+    // How the mode reads back in STAT's low 2 bits.
+    fn bits(&self) -> u8 {
+        match *self {
+            Mode::HBlank => 0,
+            Mode::VBlank => 1,
+            Mode::OamScan => 2,
+            Mode::Drawing => 3
+        }
+    }
+
+    // Mode lengths for the scanline renderer, where mode 3 is always the minimum.
+    fn dots(&self) -> u32 {
+        match *self {
+            Mode::HBlank => 204,
+            Mode::VBlank => LINE_DOTS,
+            Mode::OamScan => 80,
+            Mode::Drawing => 172
+        }
+    }
+}
+
+// Owns the LCD registers from LCDC (0xFF40) to WX (0xFF4B), except DMA (0xFF46),
+// and renders a line at a time into the framebuffer.
+pub struct Ppu {
+    renderer: Renderer,
+    fifo: Fifo,
+    lcdc: u8,
+    // Only the interrupt select bits 3-6; the rest is derived.
+    stat: u8,
+    mode: Mode,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    dots: u32,
+    // STAT interrupts fire on the rising edge of all enabled sources ORed together.
+    stat_line: bool,
+    // Set once LY has matched WY this frame, which the window needs to appear.
+    window_triggered: bool,
+    // The window only advances a line on lines it's actually drawn on.
+    window_line: u8,
+    // Shades 0-3 (lightest to darkest), after palettes have been applied.
+    framebuffer: Vec<u8>,
+    frame_ready: bool
+}
+
+// Starts in the state the boot ROM leaves it in, with the LCD on and a frame
+// just beginning.
+impl Default for Ppu {
+    fn default() -> Self {
+        Ppu {
+            renderer: Renderer::Scanline,
+            fifo: Fifo::new(),
+            lcdc: LCD_ENABLE | TILE_DATA | BG_ENABLE,
+            stat: 0x0,
+            mode: Mode::OamScan,
+            scy: 0x0,
+            scx: 0x0,
+            ly: 0x0,
+            lyc: 0x0,
+            bgp: 0xFC,
+            obp0: 0x0,
+            obp1: 0x0,
+            wy: 0x0,
+            wx: 0x0,
+            dots: 0,
+            stat_line: false,
+            window_triggered: false,
+            window_line: 0,
+            framebuffer: vec![0x0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false
+        }
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    fn read_stat(&self) -> u8 {
+        let coincidence = (self.ly == self.lyc) as u8;
+        0b1000_0000u8 | self.stat | coincidence << 2 | self.mode.bits()
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF40 => self.lcdc,
+            0xFF41 => self.read_stat(),
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => unreachable!("PPU registers only span 0xFF40-0xFF4B.")
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF40 => {
+                let was_on = self.is_lcd_on();
+                self.lcdc = value;
+                if was_on && !self.is_lcd_on() {
+                    // Switching the LCD off parks it at the start of the frame.
+                    self.ly = 0;
+                    self.dots = 0;
+                    self.mode = Mode::HBlank;
+                    self.window_triggered = false;
+                    self.window_line = 0;
+                } else if !was_on && self.is_lcd_on() {
+                    self.mode = Mode::OamScan;
+                }
+            },
+            // The mode and coincidence bits are read-only.
+            0xFF41 => self.stat = value & 0b0111_1000u8,
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            // LY is read-only.
+            0xFF44 => (),
+            0xFF45 => self.lyc = value,
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            _ => unreachable!("PPU registers only span 0xFF40-0xFF4B.")
+        }
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    // True once each time VBlank starts, meaning the framebuffer holds a whole frame.
+    pub fn take_frame_ready(&mut self) -> bool {
+        let ready = self.frame_ready;
+        self.frame_ready = false;
+        ready
+    }
+
+    fn is_lcd_on(&self) -> bool {
+        self.lcdc & LCD_ENABLE != 0
+    }
+
+    pub fn tick(&mut self, cycles: u32, vram: &[u8], oam: &[u8], interrupts: &mut Interrupts) {
+        if !self.is_lcd_on() {
+            return;
+        }
+        match self.renderer {
+            Renderer::Scanline => self.scanline_tick(cycles, vram, oam, interrupts),
+            Renderer::PixelFifo => self.fifo_tick(cycles, vram, oam, interrupts)
+        }
+    }
+
+    fn scanline_tick(&mut self, cycles: u32, vram: &[u8], oam: &[u8], interrupts: &mut Interrupts) {
+        self.dots += cycles;
+        while self.dots >= self.mode.dots() {
+            self.dots -= self.mode.dots();
+            match self.mode {
+                Mode::OamScan => self.mode = Mode::Drawing,
+                Mode::Drawing => {
+                    self.render_line(vram, oam);
+                    self.mode = Mode::HBlank;
+                },
+                Mode::HBlank | Mode::VBlank => self.next_line(interrupts)
+            }
+        }
+        self.update_stat_line(interrupts);
+    }
+
+    fn next_line(&mut self, interrupts: &mut Interrupts) {
+        self.ly += 1;
+        if self.ly as usize == SCREEN_HEIGHT {
+            self.mode = Mode::VBlank;
+            self.window_triggered = false;
+            self.window_line = 0;
+            self.frame_ready = true;
+            interrupts.request(Interrupt::VBlank);
+        } else if self.ly as usize == SCREEN_HEIGHT + VBLANK_LINES as usize {
+            self.ly = 0;
+            self.mode = Mode::OamScan;
+        } else if self.mode == Mode::HBlank {
+            self.mode = Mode::OamScan;
+        }
+    }
+
+    fn update_stat_line(&mut self, interrupts: &mut Interrupts) {
+        let mode_source = match self.mode {
+            Mode::HBlank => self.stat & HBLANK_INTERRUPT != 0,
+            Mode::VBlank => self.stat & VBLANK_INTERRUPT != 0,
+            Mode::OamScan => self.stat & OAM_SCAN_INTERRUPT != 0,
+            Mode::Drawing => false
+        };
+        let coincidence_source = self.stat & COINCIDENCE_INTERRUPT != 0 && self.ly == self.lyc;
+        let stat_line = mode_source || coincidence_source;
+        if stat_line && !self.stat_line {
+            interrupts.request(Interrupt::LcdStat);
+        }
+        self.stat_line = stat_line;
+    }
+
+    // Offset into VRAM of a BG/window tile, honouring LCDC's addressing mode.
+    fn tile_data_offset(&self, tile: u8) -> usize {
+        if self.lcdc & TILE_DATA != 0 {
+            tile as usize * 16
+        } else {
+            // Signed tile numbers, relative to 0x9000.
+            (0x1000 + (tile as i8 as i32) * 16) as usize
+        }
+    }
+
+    // The 2-bit colour number of pixel (x, y) within the tile at tile_offset.
+    fn tile_color(vram: &[u8], tile_offset: usize, x: usize, y: usize) -> u8 {
+        let lo = vram[tile_offset + y * 2];
+        let hi = vram[tile_offset + y * 2 + 1];
+        let bit = 7 - x;
+        ((hi >> bit) & 1) << 1 | (lo >> bit) & 1
+    }
+
+    // Offset into VRAM of the tile covering pixel (x, y) of a 256x256 tile map.
+    fn tile_map_offset(&self, vram: &[u8], map_select: u8, x: usize, y: usize) -> usize {
+        let map_offset = if self.lcdc & map_select != 0 { 0x1C00 } else { 0x1800 };
+        let tile = vram[map_offset + (y / 8) * 32 + x / 8];
+        self.tile_data_offset(tile)
+    }
+
+    fn tile_map_color(&self, vram: &[u8], map_select: u8, x: usize, y: usize) -> u8 {
+        Self::tile_color(vram, self.tile_map_offset(vram, map_select, x, y), x % 8, y % 8)
+    }
+
+    fn shade(palette: u8, color: u8) -> u8 {
+        (palette >> (color * 2)) & 0b11
+    }
+
+    fn render_line(&mut self, vram: &[u8], oam: &[u8]) {
+        let ly = self.ly as usize;
+        if self.ly == self.wy {
+            self.window_triggered = true;
+        }
+        // Colour numbers before the palette, which sprite priority depends on.
+        let mut bg_colors = [0u8; SCREEN_WIDTH];
+        // On DMG, clearing BG_ENABLE blanks both the background and the window.
+        if self.lcdc & BG_ENABLE != 0 {
+            let window_visible = self.lcdc & WINDOW_ENABLE != 0
+                && self.window_triggered
+                && self.wx <= 166;
+            let mut window_drawn = false;
+            for x in 0 .. SCREEN_WIDTH {
+                // WX is offset by 7, so the window starts at screen column WX-7.
+                bg_colors[x] = if window_visible && x + 7 >= self.wx as usize {
+                    window_drawn = true;
+                    let window_line = self.window_line as usize;
+                    self.tile_map_color(vram, WINDOW_TILE_MAP, x + 7 - self.wx as usize, window_line)
+                } else {
+                    let bg_x = (x + self.scx as usize) % 256;
+                    let bg_y = (ly + self.scy as usize) % 256;
+                    self.tile_map_color(vram, BG_TILE_MAP, bg_x, bg_y)
+                };
+            }
+            if window_drawn {
+                self.window_line += 1;
+            }
+        }
+        for x in 0 .. SCREEN_WIDTH {
+            self.framebuffer[ly * SCREEN_WIDTH + x] = Self::shade(self.bgp, bg_colors[x]);
+        }
+        if self.lcdc & OBJ_ENABLE != 0 {
+            self.render_sprites(vram, oam, &bg_colors);
+        }
+    }
+
+    fn sprite_height(&self) -> i32 {
+        if self.lcdc & OBJ_SIZE != 0 { 16 } else { 8 }
+    }
+
+    // OAM scan picks the first 10 sprites in OAM order that overlap this line.
+    fn oam_scan(&self, oam: &[u8]) -> Vec<[u8; 4]> {
+        let ly = self.ly as i32;
+        let height = self.sprite_height();
+        oam.chunks(4)
+            .filter(|sprite| {
+                let top = sprite[0] as i32 - 16;
+                ly >= top && ly < top + height
+            })
+            .take(SPRITES_PER_LINE)
+            .map(|sprite| [sprite[0], sprite[1], sprite[2], sprite[3]])
+            .collect()
+    }
+
+    // The colour number of one of the sprite's 8 columns on the current line.
+    fn sprite_color(&self, vram: &[u8], sprite: &[u8; 4], column: usize) -> u8 {
+        let height = self.sprite_height();
+        let attributes = sprite[3];
+        let mut row = self.ly as i32 - (sprite[0] as i32 - 16);
+        if attributes & OBJ_FLIP_Y != 0 {
+            row = height - 1 - row;
+        }
+        // 8x16 sprites ignore the tile number's low bit.
+        let tile = if height == 16 { sprite[2] & 0xFE } else { sprite[2] };
+        let tile_x = if attributes & OBJ_FLIP_X != 0 { 7 - column } else { column };
+        Self::tile_color(vram, tile as usize * 16, tile_x, row as usize)
+    }
+
+    fn render_sprites(&mut self, vram: &[u8], oam: &[u8], bg_colors: &[u8]) {
+        let mut sprites = self.oam_scan(oam);
+        // Lower X wins, with ties going to whichever comes first in OAM.
+        sprites.sort_by_key(|sprite| sprite[1]);
+        let mut drawn = [false; SCREEN_WIDTH];
+        for sprite in sprites {
+            let left = sprite[1] as i32 - 8;
+            let attributes = sprite[3];
+            let palette = if attributes & OBJ_PALETTE != 0 { self.obp1 } else { self.obp0 };
+            for column in 0 .. 8 {
+                let x = left + column as i32;
+                if x < 0 || x >= SCREEN_WIDTH as i32 || drawn[x as usize] {
+                    continue;
+                }
+                let x = x as usize;
+                let color = self.sprite_color(vram, &sprite, column);
+                // Colour 0 is transparent, letting lower priority sprites show through.
+                if color == 0 {
+                    continue;
+                }
+                drawn[x] = true;
+                if attributes & OBJ_BEHIND_BG != 0 && bg_colors[x] != 0 {
+                    continue;
+                }
+                self.framebuffer[self.ly as usize * SCREEN_WIDTH + x] = Self::shade(palette, color);
+            }
+        }
+    }
+}
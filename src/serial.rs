@@ -0,0 +1,55 @@
+use interrupt::*;
+
+// A transfer shifts out 8 bits at 8192 Hz when using the internal clock.
+const TRANSFER_CYCLES: u32 = 4096;
+
+// Owns SB (0xFF01) and SC (0xFF02). There's never a link partner, so anything
+// shifted in reads as 1s.
+#[derive(Default)]
+pub struct Serial {
+    data: u8,
+    control: u8,
+    transfer_cycles: u32
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.data,
+            0xFF02 => 0b0111_1110u8 | self.control,
+            _ => unreachable!("Serial registers only span 0xFF01-0xFF02.")
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF01 => self.data = value,
+            0xFF02 => {
+                self.control = value & 0b1000_0001u8;
+                self.transfer_cycles = 0;
+            },
+            _ => unreachable!("Serial registers only span 0xFF01-0xFF02.")
+        }
+    }
+
+    fn is_transferring(&self) -> bool {
+        // With an external clock and nothing connected, the transfer never finishes.
+        self.control == 0b1000_0001u8
+    }
+
+    pub fn tick(&mut self, cycles: u32, interrupts: &mut Interrupts) {
+        if !self.is_transferring() {
+            return;
+        }
+        self.transfer_cycles += cycles;
+        if self.transfer_cycles >= TRANSFER_CYCLES {
+            self.data = 0xFF;
+            self.control &= 0b0111_1111u8;
+            interrupts.request(Interrupt::Serial);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use interrupt::*;
+
+// The timer's state machine advances once per machine cycle.
+const STEP_CYCLES: u32 = 4;
+
+// Owns DIV (0xFF04), TIMA (0xFF05), TMA (0xFF06) and TAC (0xFF07).
+#[derive(Default)]
+pub struct Timer {
+    // DIV is the upper byte of this internal counter, which counts every cycle.
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA reads 0 for a machine cycle after overflowing, before TMA is loaded.
+    reload_pending: bool,
+    // Set for the machine cycle in which TMA is copied into TIMA.
+    reloading: bool,
+    leftover_cycles: u32
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => 0b1111_1000u8 | self.tac,
+            _ => unreachable!("Timer registers only span 0xFF04-0xFF07.")
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            // Any write resets the whole counter, not just the visible byte.
+            0xFF04 => {
+                let was_high = self.input();
+                self.counter = 0;
+                self.falling_edge(was_high);
+            },
+            0xFF05 => {
+                // Writing during the reload cycle loses out to TMA, while
+                // writing during the delay before it cancels the reload.
+                if !self.reloading {
+                    self.tima = value;
+                    self.reload_pending = false;
+                }
+            },
+            0xFF06 => {
+                self.tma = value;
+                if self.reloading {
+                    self.tima = value;
+                }
+            },
+            0xFF07 => {
+                let was_high = self.input();
+                self.tac = value & 0b0000_0111u8;
+                self.falling_edge(was_high);
+            },
+            _ => unreachable!("Timer registers only span 0xFF04-0xFF07.")
+        }
+    }
+
+    // STOP resets DIV like any write to it would.
+    pub fn reset_div(&mut self) {
+        self.write(0xFF04, 0x0);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.tac & 0b0000_0100u8 != 0
+    }
+
+    // The counter bit TIMA watches for each TAC frequency: 4096, 262144, 65536
+    // and 16384 Hz respectively.
+    fn input_bit(&self) -> u16 {
+        match self.tac & 0b0000_0011u8 {
+            0b00 => 9,
+            0b01 => 3,
+            0b10 => 5,
+            0b11 => 7,
+            _ => unreachable!()
+        }
+    }
+
+    // TIMA is clocked by the selected counter bit ANDed with the enable bit.
+    fn input(&self) -> bool {
+        self.is_enabled() && (self.counter >> self.input_bit()) & 1 != 0
+    }
+
+    // Anything that makes the input drop from 1 to 0 increments TIMA, which is
+    // why writing DIV or TAC can tick the timer early.
+    fn falling_edge(&mut self, was_high: bool) {
+        if was_high && !self.input() {
+            self.increment_tima();
+        }
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflowed) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflowed {
+            self.reload_pending = true;
+        }
+    }
+
+    fn step(&mut self, interrupts: &mut Interrupts) {
+        self.reloading = false;
+        if self.reload_pending {
+            self.reload_pending = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            interrupts.request(Interrupt::Timer);
+        }
+        let was_high = self.input();
+        self.counter = self.counter.wrapping_add(STEP_CYCLES as u16);
+        self.falling_edge(was_high);
+    }
+
+    pub fn tick(&mut self, cycles: u32, interrupts: &mut Interrupts) {
+        let cycles = self.leftover_cycles + cycles;
+        for _ in 0 .. cycles / STEP_CYCLES {
+            self.step(interrupts);
+        }
+        self.leftover_cycles = cycles % STEP_CYCLES;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mem::*;
+    use interrupt::*;
+
+    // TIMA enabled at 262144 Hz, so it increments every 16 cycles.
+    fn mem_with_fast_timer() -> Mem {
+        let mut mem = Mem::new();
+        mem.write_u8(0xFF06, 0xAB);
+        mem.write_u8(0xFF07, 0x05);
+        mem
+    }
+
+    // Runs TIMA up to the point it overflows, without reloading it yet.
+    fn overflow(mem: &mut Mem) {
+        mem.write_u8(0xFF05, 0xFF);
+        mem.tick(16);
+    }
+
+    #[test]
+    fn tima_reloads_a_machine_cycle_after_overflowing() {
+        let mut mem = mem_with_fast_timer();
+        overflow(&mut mem);
+        assert_eq!(mem.read_u8(0xFF05), 0x00);
+        assert!(!mem.is_interrupt_requested(Interrupt::Timer));
+        mem.tick(4);
+        assert_eq!(mem.read_u8(0xFF05), 0xAB);
+        assert!(mem.is_interrupt_requested(Interrupt::Timer));
+    }
+
+    #[test]
+    fn writing_tima_before_the_reload_cancels_it() {
+        let mut mem = mem_with_fast_timer();
+        overflow(&mut mem);
+        mem.write_u8(0xFF05, 0x12);
+        mem.tick(4);
+        assert_eq!(mem.read_u8(0xFF05), 0x12);
+        assert!(!mem.is_interrupt_requested(Interrupt::Timer));
+    }
+
+    #[test]
+    fn writes_during_the_reload_cycle() {
+        let mut mem = mem_with_fast_timer();
+        overflow(&mut mem);
+        mem.tick(4);
+        // TMA wins over a TIMA write, and a TMA write goes straight through.
+        mem.write_u8(0xFF05, 0x12);
+        assert_eq!(mem.read_u8(0xFF05), 0xAB);
+        mem.write_u8(0xFF06, 0xCD);
+        assert_eq!(mem.read_u8(0xFF05), 0xCD);
+        // Once the cycle is over, TIMA can be written again.
+        mem.tick(4);
+        mem.write_u8(0xFF05, 0x12);
+        assert_eq!(mem.read_u8(0xFF05), 0x12);
+    }
+
+    #[test]
+    fn div_and_tac_writes_can_increment_tima() {
+        let mut mem = mem_with_fast_timer();
+        mem.write_u8(0xFF05, 0x00);
+        // Bit 3 of the counter is now set, so resetting DIV makes it fall.
+        mem.tick(8);
+        mem.write_u8(0xFF04, 0x00);
+        assert_eq!(mem.read_u8(0xFF05), 0x01);
+        // Disabling the timer drops the input too.
+        mem.tick(8);
+        mem.write_u8(0xFF07, 0x01);
+        assert_eq!(mem.read_u8(0xFF05), 0x02);
+        // With bit 3 clear, neither does anything.
+        mem.write_u8(0xFF04, 0x00);
+        mem.write_u8(0xFF07, 0x05);
+        mem.write_u8(0xFF04, 0x00);
+        mem.write_u8(0xFF07, 0x01);
+        assert_eq!(mem.read_u8(0xFF05), 0x02);
+    }
+}